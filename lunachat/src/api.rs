@@ -0,0 +1,165 @@
+use axum::Json;
+use axum::body::to_bytes;
+use axum::extract::Request;
+use axum::http::StatusCode;
+use axum::http::header::{ACCEPT, CONTENT_TYPE};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use serde::Serialize;
+
+use crate::auth::{AuthSession, Permission};
+use crate::negotiate::Format;
+use crate::prelude::*;
+use crate::templates::partial::{PartialPostGet, PartialThreadGet};
+
+/// JSON body returned by `/api/*` routes on failure.
+#[derive(Serialize)]
+pub struct ApiError {
+    pub error: String,
+    pub code: u16,
+    /// Where to log in, on 401s.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub login_url: Option<String>,
+}
+
+impl ApiError {
+    pub fn response(status: StatusCode, error: impl Into<String>) -> Response {
+        (
+            status,
+            Json(ApiError {
+                error: error.into(),
+                code: status.as_u16(),
+                login_url: None,
+            }),
+        )
+            .into_response()
+    }
+
+    /// 401 for a client that isn't logged in, or whose session has expired.
+    pub fn unauthorized() -> Response {
+        let status = StatusCode::UNAUTHORIZED;
+        (
+            status,
+            Json(ApiError {
+                error: "Not logged in".into(),
+                code: status.as_u16(),
+                login_url: Some(crate::url("/login")),
+            }),
+        )
+            .into_response()
+    }
+}
+
+/// Answers anonymous requests with [`ApiError::unauthorized`] instead of the login redirect
+/// HTML routes get, which an API client can't follow.
+pub async fn require_login(auth: AuthSession, req: Request, next: Next) -> Response {
+    if auth.user.is_none() {
+        return ApiError::unauthorized();
+    }
+    next.run(req).await
+}
+
+/// Largest plaintext error body that gets rewritten as JSON. Anything bigger isn't one of
+/// our error messages.
+const ERROR_BODY_LIMIT: usize = 64 * 1024;
+
+/// Rewrites plaintext error responses as [`ApiError`] JSON for `/api/*` routes and for
+/// clients that prefer JSON, since handler errors only know how to render as text.
+pub async fn json_errors(req: Request, next: Next) -> Response {
+    let wants_json = req.uri().path().starts_with(&crate::url("/api/"))
+        || req
+            .headers()
+            .get(ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+            .is_some_and(|accept| Format::from_accept(accept) == Format::Json);
+    let response = next.run(req).await;
+
+    let status = response.status();
+    let is_plaintext = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_none_or(|content_type| content_type.starts_with("text/plain"));
+    if !wants_json || !(status.is_client_error() || status.is_server_error()) || !is_plaintext {
+        return response;
+    }
+
+    match to_bytes(response.into_body(), ERROR_BODY_LIMIT).await {
+        Ok(body) => ApiError::response(status, String::from_utf8_lossy(&body)),
+        Err(_) => ApiError::response(status, status.canonical_reason().unwrap_or_default()),
+    }
+}
+
+/// A user as exposed over the API, without credentials.
+#[derive(Clone, Serialize)]
+pub struct PublicUser {
+    pub id: user::Id,
+    pub username: String,
+    pub avatar: Option<String>,
+    pub signature: Option<String>,
+}
+
+impl From<user::Model> for PublicUser {
+    fn from(user: user::Model) -> Self {
+        Self {
+            id: user.id,
+            username: user.username,
+            avatar: user.avatar,
+            signature: user.signature,
+        }
+    }
+}
+
+/// The logged-in user and what they're allowed to do, returned by `/whoami`.
+#[derive(Serialize)]
+pub struct Whoami {
+    pub user: PublicUser,
+    pub permissions: Vec<Permission>,
+}
+
+#[derive(Serialize)]
+pub struct ThreadSummary {
+    pub thread: thread::Model,
+    pub post: post::Model,
+    pub author: PublicUser,
+    pub tags: Vec<String>,
+    pub num_posts: u64,
+    pub last_reply: Option<PostWithAuthor>,
+}
+
+impl From<PartialThreadGet> for ThreadSummary {
+    fn from(template: PartialThreadGet) -> Self {
+        Self {
+            thread: template.thread,
+            post: template.post,
+            author: template.author.into(),
+            tags: template.tags,
+            num_posts: template.num_posts,
+            last_reply: template.last_reply.map(|(post, author)| PostWithAuthor {
+                post,
+                author: author.into(),
+            }),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct PostWithAuthor {
+    pub post: post::Model,
+    pub author: PublicUser,
+}
+
+impl From<PartialPostGet> for PostWithAuthor {
+    fn from(template: PartialPostGet) -> Self {
+        Self {
+            post: template.post,
+            author: template.author.into(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct ThreadWithPosts {
+    pub thread: thread::Model,
+    pub posts: Vec<PostWithAuthor>,
+}
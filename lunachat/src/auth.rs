@@ -1,13 +1,18 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::ops::RangeInclusive;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
 
 use async_trait::async_trait;
 use axum_login::{AuthUser, AuthnBackend, AuthzBackend};
 use derive_more::Display;
 use password_auth::verify_password;
 use return_ok::ok_some;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 use crate::prelude::*;
+use crate::validation::{FieldErrors, Validate};
 
 impl AuthUser for user::Model {
     type Id = user::Id;
@@ -24,11 +29,108 @@ impl AuthUser for user::Model {
 #[derive(Clone)]
 pub struct Backend {
     db: DatabaseConnection,
+    lockout: LoginLockout,
+    thread_creators: ThreadCreators,
 }
 
 impl Backend {
-    pub fn new(db: DatabaseConnection) -> Self {
-        Self { db }
+    pub fn new(
+        db: DatabaseConnection,
+        lockout: LoginLockout,
+        thread_creators: ThreadCreators,
+    ) -> Self {
+        Self {
+            db,
+            lockout,
+            thread_creators,
+        }
+    }
+}
+
+/// Who may start new threads, from `LUNACHAT_THREAD_CREATORS`. Everyone who can log in
+/// may still reply.
+#[derive(Clone, Copy, Default)]
+pub enum ThreadCreators {
+    #[default]
+    Members,
+    Moderators,
+}
+
+impl ThreadCreators {
+    pub fn from_env() -> Result<Self> {
+        match env::var("LUNACHAT_THREAD_CREATORS").as_deref() {
+            Ok("members") | Err(_) => Ok(ThreadCreators::Members),
+            Ok("moderators") => Ok(ThreadCreators::Moderators),
+            Ok(other) => Err(anyhow!(
+                "LUNACHAT_THREAD_CREATORS must be members or moderators, not {other}"
+            )),
+        }
+    }
+}
+
+/// Failed login attempts, keyed on the username that was tried rather than on an
+/// account, so guessing at usernames that don't exist is throttled too.
+#[derive(Clone)]
+pub struct LoginLockout {
+    max_failures: u32,
+    window: Duration,
+    failures: Arc<Mutex<HashMap<String, (u32, Instant)>>>,
+}
+
+impl LoginLockout {
+    /// Locks a username out after `LUNACHAT_LOGIN_MAX_FAILURES` failures (default 5)
+    /// within `LUNACHAT_LOGIN_LOCKOUT_SECS` (default 15 minutes) of the first one.
+    pub fn from_env() -> Result<Self> {
+        let max_failures = match env::var("LUNACHAT_LOGIN_MAX_FAILURES") {
+            Ok(max) => max.parse()?,
+            Err(_) => 5,
+        };
+        let window = match env::var("LUNACHAT_LOGIN_LOCKOUT_SECS") {
+            Ok(secs) => Duration::from_secs(secs.parse()?),
+            Err(_) => Duration::from_secs(15 * 60),
+        };
+        Ok(Self {
+            max_failures,
+            window,
+            failures: Default::default(),
+        })
+    }
+
+    fn key(username: &str) -> String {
+        username.trim().to_lowercase()
+    }
+
+    /// How long until `username` may try again, if it's locked out.
+    pub fn retry_after(&self, username: &str) -> Option<Duration> {
+        let failures = self.failures.lock().unwrap_or_else(PoisonError::into_inner);
+        let &(count, first) = failures.get(&Self::key(username))?;
+        if count < self.max_failures {
+            return None;
+        }
+        self.window.checked_sub(first.elapsed())
+    }
+
+    pub fn record_failure(&self, username: &str) {
+        let mut failures = self.failures.lock().unwrap_or_else(PoisonError::into_inner);
+        failures.retain(|_, (_, first)| first.elapsed() < self.window);
+        failures
+            .entry(Self::key(username))
+            .or_insert((0, Instant::now()))
+            .0 += 1;
+    }
+
+    pub fn reset(&self, username: &str) {
+        let mut failures = self.failures.lock().unwrap_or_else(PoisonError::into_inner);
+        failures.remove(&Self::key(username));
+    }
+}
+
+/// Whether anonymous visitors may read the forum, from `LUNACHAT_PUBLIC_READ`. Defaults
+/// to true.
+pub fn public_read() -> Result<bool> {
+    match env::var("LUNACHAT_PUBLIC_READ") {
+        Ok(public_read) => Ok(public_read.parse()?),
+        Err(_) => Ok(true),
     }
 }
 
@@ -39,25 +141,69 @@ pub struct Credentials {
     pub next: Option<String>,
 }
 
-#[derive(Clone, PartialEq, Eq, Hash)]
+/// Registration rules. Logging in doesn't check these, so accounts made before a rule
+/// changed can still get in.
+impl Validate for Credentials {
+    fn validate(&self) -> FieldErrors {
+        const USERNAME_LEN: RangeInclusive<usize> = 3..=32;
+        const MIN_PASSWORD_LEN: usize = 8;
+
+        let mut errors = FieldErrors::default();
+        if !USERNAME_LEN.contains(&self.username.chars().count()) {
+            errors.add(
+                "username",
+                format!(
+                    "Usernames are {} to {} characters long",
+                    USERNAME_LEN.start(),
+                    USERNAME_LEN.end()
+                ),
+            );
+        }
+        if !crate::unicode::is_plain_username(&self.username) {
+            errors.add(
+                "username",
+                "Usernames can't contain hidden or lookalike characters",
+            );
+        }
+        if self.password.chars().count() < MIN_PASSWORD_LEN {
+            errors.add(
+                "password",
+                format!("Passwords need at least {MIN_PASSWORD_LEN} characters"),
+            );
+        }
+        errors
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, Serialize)]
 pub enum Permission {
-    Post,
+    /// Starting new threads.
+    CreateThread,
+    /// Replying to existing threads, and the drafts, previews and signatures that go with it.
+    Reply,
+    Moderate,
 }
 
 #[derive(Debug, Display)]
-pub struct AuthError(pub Error);
+pub enum AuthError {
+    /// The username is locked out by [`LoginLockout`] for this long.
+    #[display("Too many failed logins, try again later")]
+    TooManyAttempts(Duration),
+    #[display("{_0}")]
+    Other(Error),
+}
 
 impl std::error::Error for AuthError {}
 
 impl From<sea_orm::DbErr> for AuthError {
     fn from(err: sea_orm::DbErr) -> Self {
-        AuthError(err.into())
+        AuthError::Other(err.into())
     }
 }
 
 impl From<tokio::task::JoinError> for AuthError {
     fn from(err: tokio::task::JoinError) -> Self {
-        AuthError(err.into())
+        AuthError::Other(err.into())
     }
 }
 
@@ -71,16 +217,24 @@ impl AuthnBackend for Backend {
         &self,
         creds: Self::Credentials,
     ) -> Result<Option<Self::User>, Self::Error> {
-        let user = ok_some!(self.db.find_user_by_username(creds.username).await);
-
-        Ok(tokio::task::spawn_blocking(|| {
-            if verify_password(creds.password, &user.password).is_ok() {
-                Some(user)
-            } else {
-                None
-            }
+        // Checked before the password, so a locked-out username can't be used to tell a
+        // right guess from a wrong one.
+        if let Some(retry_after) = self.lockout.retry_after(&creds.username) {
+            return Err(AuthError::TooManyAttempts(retry_after));
+        }
+
+        let username = creds.username.clone();
+        let user = self.db.find_user_by_username(creds.username).await?;
+        let user = tokio::task::spawn_blocking(|| {
+            user.filter(|user| verify_password(creds.password, &user.password).is_ok())
         })
-        .await?)
+        .await?;
+
+        match &user {
+            Some(_) => self.lockout.reset(&username),
+            None => self.lockout.record_failure(&username),
+        }
+        Ok(user)
     }
 
     async fn get_user(
@@ -97,10 +251,16 @@ impl AuthzBackend for Backend {
 
     async fn get_user_permissions(
         &self,
-        _user: &Self::User,
+        user: &Self::User,
     ) -> Result<HashSet<Self::Permission>, Self::Error> {
         let mut permissions = HashSet::new();
-        permissions.insert(Permission::Post);
+        permissions.insert(Permission::Reply);
+        if user.moderator || matches!(self.thread_creators, ThreadCreators::Members) {
+            permissions.insert(Permission::CreateThread);
+        }
+        if user.moderator {
+            permissions.insert(Permission::Moderate);
+        }
         Ok(permissions)
     }
 }
@@ -111,3 +271,126 @@ pub type AuthSession = axum_login::AuthSession<Backend>;
 pub struct NextUrl {
     pub next: Option<String>,
 }
+
+/// Restricts a post-login redirect target to a path on this site, falling back to the
+/// forum index. Rejects absolute URLs, scheme-relative `//host` paths (including the
+/// `/\host` form browsers normalize to it) and anything with control characters.
+pub fn sanitize_next(next: Option<String>) -> String {
+    match next {
+        Some(next)
+            if next.starts_with('/')
+                && !next[1..].starts_with(['/', '\\'])
+                && !next.chars().any(|c| c.is_control()) =>
+        {
+            next
+        }
+        _ => crate::url("/"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn next(next: &str) -> String {
+        sanitize_next(Some(next.into()))
+    }
+
+    #[test]
+    fn same_site_paths_are_kept() {
+        assert_eq!(next("/thread/3"), "/thread/3");
+        assert_eq!(next("/thread/3?page=2#post_9"), "/thread/3?page=2#post_9");
+    }
+
+    #[test]
+    fn scheme_relative_paths_are_refused() {
+        assert_eq!(next("//evil.com"), crate::url("/"));
+        assert_eq!(next("/\\evil.com"), crate::url("/"));
+    }
+
+    #[test]
+    fn absolute_urls_are_refused() {
+        assert_eq!(next("https://evil.com"), crate::url("/"));
+        assert_eq!(next("javascript:alert(1)"), crate::url("/"));
+        assert_eq!(next("evil.com/thread/3"), crate::url("/"));
+    }
+
+    #[test]
+    fn control_characters_are_refused() {
+        // Browsers drop tabs and newlines from URLs, which would turn these into `//evil.com`.
+        assert_eq!(next("/\t/evil.com"), crate::url("/"));
+        assert_eq!(next("/\n/evil.com"), crate::url("/"));
+        assert_eq!(next("/thread/3\r\nSet-Cookie: x=y"), crate::url("/"));
+    }
+
+    #[test]
+    fn missing_next_goes_home() {
+        assert_eq!(sanitize_next(None), crate::url("/"));
+        assert_eq!(next(""), crate::url("/"));
+    }
+
+    fn lockout(max_failures: u32, window: Duration) -> LoginLockout {
+        LoginLockout {
+            max_failures,
+            window,
+            failures: Default::default(),
+        }
+    }
+
+    #[test]
+    fn lockout_starts_at_the_limit() {
+        let lockout = lockout(3, Duration::from_secs(60));
+        for _ in 0..2 {
+            lockout.record_failure("luna");
+        }
+        assert_eq!(lockout.retry_after("luna"), None);
+        lockout.record_failure("luna");
+        let retry_after = lockout.retry_after("luna").unwrap();
+        assert!(retry_after <= Duration::from_secs(60));
+        assert_eq!(lockout.retry_after("someone_else"), None);
+    }
+
+    #[test]
+    fn lockout_ignores_case_and_whitespace() {
+        let lockout = lockout(2, Duration::from_secs(60));
+        lockout.record_failure("Luna");
+        lockout.record_failure(" luna ");
+        assert!(lockout.retry_after("LUNA").is_some());
+    }
+
+    #[test]
+    fn successful_login_resets_failures() {
+        let lockout = lockout(2, Duration::from_secs(60));
+        lockout.record_failure("luna");
+        lockout.reset("Luna");
+        lockout.record_failure("luna");
+        assert_eq!(lockout.retry_after("luna"), None);
+    }
+
+    #[test]
+    fn lockout_expires_after_the_window() {
+        let window = Duration::from_secs(60);
+        let lockout = lockout(1, window);
+        lockout.record_failure("luna");
+        assert!(lockout.retry_after("luna").is_some());
+        // Backdated rather than waited out, so a slow run can't expire it early.
+        let expired = Instant::now() - window - Duration::from_secs(1);
+        lockout
+            .failures
+            .lock()
+            .unwrap()
+            .insert("luna".into(), (1, expired));
+        assert_eq!(lockout.retry_after("luna"), None);
+        // Old failures don't count towards a new lockout.
+        lockout.record_failure("someone_else");
+        assert!(lockout.failures.lock().unwrap().get("luna").is_none());
+    }
+
+    #[test]
+    fn deleted_accounts_cant_log_in() {
+        let deleted = user::Model::deleted(Default::default());
+        for password in ["", "!", "hunter2"] {
+            assert!(verify_password(password, &deleted.password).is_err());
+        }
+    }
+}
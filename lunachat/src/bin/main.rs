@@ -1,63 +1,165 @@
+use std::net::SocketAddr;
+
 use askama::Template;
 use awesome_axum_responses::*;
-use axum::extract::FromRequestParts;
-use axum::http::Uri;
+use axum::extract::{FromRequestParts, OriginalUri};
+use axum::http::header::{CONTENT_TYPE, LINK, LOCATION, RETRY_AFTER};
 use axum::http::request::Parts;
-use axum::response::{IntoResponse, Redirect};
-use axum::routing::{get, post};
-use axum::{RequestPartsExt, Router};
+use axum::http::{HeaderMap, HeaderName, HeaderValue, StatusCode, Uri};
+use axum::response::{Html, IntoResponse, Redirect, Response};
+use axum::routing::{delete, get, post};
+use axum::{Extension, Json, RequestPartsExt, Router, middleware};
 use axum_htmx::HxBoosted;
-use axum_login::{AuthzBackend as _, permission_required};
+use axum_login::{AuthzBackend as _, login_required, permission_required};
+use chrono::{DateTime, Utc};
 use itertools::Itertools;
+use lunachat::api::{ApiError, PostWithAuthor, PublicUser, ThreadSummary, ThreadWithPosts, Whoami};
 use lunachat::auth::{AuthSession, Backend, Permission};
+use lunachat::maintenance::Maintenance;
+use lunachat::metrics::{METRICS, MetricsToken};
+use lunachat::negotiate::{Format, Negotiated};
 use lunachat::prelude::*;
+use lunachat::render_cache::RenderCache;
+use lunachat::robots::RobotsPolicy;
+use lunachat::sanitizer::{Formatting, Sanitizer};
+use lunachat::submission::SubmissionError;
 use lunachat::templates::partial::{PostSse, ThreadSse};
 use lunachat::templates::{
-    ForumGet, LoginGet, LoginPost, LogoutPost, PostPost, RegisterPost, ThreadGet, ThreadPost,
-    UserGet,
+    AcceptAnswerPost, DraftGet, DraftPut, ForumFeedGet, ForumGet, LoginGet, LoginPost, LogoutPost,
+    MaintenancePost, PostApprovePost, PostGet, PostPost, PreviewPost, RegisterPost, ShowSignatures,
+    SignaturePost, TagGet, ThreadFeedGet, ThreadGet, ThreadPinPost, ThreadPost, ThreadPosts,
+    ThreadsPageGet, TimezonePost, UserDeleteAdminPost, UserDeletePost, UserGet, UserSearchGet,
 };
+use lunachat::timezone::ViewerTimezone;
+use lunachat::validation::{FieldError, FieldErrors};
+use lunachat::webhooks::{ModerationAction, WebhookEvent, Webhooks};
+use lunachat::{absolute_url, url};
 use tower_http::services::ServeDir;
+use tower_http::timeout::TimeoutLayer;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    tracing_subscriber::fmt()
-        .with_env_filter("debug,lunachat=trace,main=trace,sqlx=warn")
-        .init();
+    lunachat::logging::init()?;
 
-    let app = Router::new()
+    self_check()?;
+
+    let api = Router::new()
+        .route("/threads", get(api_threads))
+        .route("/formatting", get(api_formatting))
+        .route("/users/search", get(user_search));
+
+    let admin = Router::new()
+        .route("/admin/post/{post_key}/approve", post(post_approve))
+        .route("/admin/thread/{thread_key}/pin", post(thread_pin))
+        .route("/admin/maintenance", post(maintenance))
+        .route("/admin/user/{user_key}/delete", post(user_delete_admin))
+        .route_layer(permission_required!(
+            Backend,
+            login_url = &url("/login"),
+            Permission::Moderate
+        ));
+
+    let read = Router::new()
+        .route("/", get(forum))
+        .route("/feed.xml", get(forum_feed))
+        .route("/thread/{thread_key}", get(thread))
+        .route("/thread/{thread_key}/feed.xml", get(thread_feed))
+        .route("/thread/{thread_key}/post/{post_key}", get(post_fragment))
+        .route("/tag/{tag}", get(tag))
+        .route("/formatting", get(formatting))
+        .route("/user/{user_key}", get(user));
+    // Kept apart from the rest so the request timeout doesn't cut them off.
+    let streams = Router::new()
+        .route("/sse", get(forum_sse))
+        .route("/thread/{thread_key}/sse", get(thread_sse));
+    let (read, streams, api) = if lunachat::auth::public_read()? {
+        (read, streams, api)
+    } else {
+        (
+            read.route_layer(login_required!(Backend, login_url = &url("/login"))),
+            streams.route_layer(middleware::from_fn(lunachat::sse::require_login)),
+            api.route_layer(middleware::from_fn(lunachat::api::require_login)),
+        )
+    };
+    let api = api.layer(lunachat::cors::layer()?);
+
+    let create_thread = Router::new()
         .route("/thread", post(thread_post))
+        .route("/draft", get(draft_get).put(draft_put))
+        .route_layer(permission_required!(
+            Backend,
+            login_url = &url("/login"),
+            Permission::CreateThread
+        ));
+
+    let account = Router::new()
+        .route("/user/delete", post(user_delete))
+        .route_layer(login_required!(Backend, login_url = &url("/login")));
+
+    let app = Router::new()
         .route("/thread/{thread_key}", post(post_post))
+        .route("/preview", post(preview_post))
+        .route("/user/signature", post(signature_post))
+        .route("/user/timezone", post(timezone_post))
+        .route("/thread/{thread_key}/draft", get(draft_get).put(draft_put))
+        .route(
+            "/thread/{thread_key}/accept/{post_key}",
+            post(accept_answer),
+        )
+        .route("/thread/{thread_key}/accept", delete(accept_answer))
         .route_layer(permission_required!(
             Backend,
-            login_url = "/login",
-            Permission::Post
+            login_url = &url("/login"),
+            Permission::Reply
         ))
-        .route("/", get(forum))
-        .route("/sse", get(forum_sse))
-        .route("/thread/{thread_key}", get(thread))
-        .route("/thread/{thread_key}/sse", get(thread_sse))
-        .route("/user/{user_key}", get(user))
+        .merge(create_thread)
+        .merge(admin)
+        .merge(read)
+        .merge(account)
         .route("/login", get(login))
         .route("/login", post(login_post))
         .route("/logout", get(logout_post))
         .route("/register", post(register_post))
-        .nest_service("/static", ServeDir::new("static"));
+        .route("/whoami", get(whoami))
+        .route("/metrics", get(metrics))
+        .nest("/api", api)
+        .route("/favicon.ico", get(favicon))
+        .route("/robots.txt", get(robots))
+        .nest_service("/static", ServeDir::new("static"))
+        .fallback(not_found)
+        .layer(TimeoutLayer::with_status_code(
+            StatusCode::GATEWAY_TIMEOUT,
+            lunachat::request_timeout()?,
+        ))
+        .merge(streams);
+    let app = match lunachat::base_path() {
+        "" => app,
+        base_path => Router::new().nest(base_path, app),
+    };
     let app = lunachat::apply_middleware(app).await?;
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:80").await?;
     tracing::info!("Lunachat started!");
-    axum::serve(listener, app).await?;
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
 
     Ok(())
 }
 
 async fn forum(
+    format: Format,
     logged_in: LoggedIn,
+    Extension(maintenance): Extension<Maintenance>,
     auth: AuthSession,
+    timezone: ViewerTimezone,
     forum: ForumGet,
 ) -> Result<impl IntoResponse> {
-    Ok(HtmlTemplate(ForumTemplate {
+    let html = ForumTemplate {
         logged_in,
+        maintenance: maintenance.is_active(),
         threads: forum
             .threads
             .iter()
@@ -66,126 +168,906 @@ async fn forum(
                 thread: template.thread,
                 post: template.post,
                 author: template.author,
+                tags: template.tags,
+                num_posts: template.num_posts,
+                last_reply: template.last_reply,
                 sse: false,
+                timezone,
             })
             .join("\n"),
-        can_post: match auth.user {
-            Some(user) => auth.backend.has_perm(&user, Permission::Post).await?,
+        can_create_thread: match auth.user {
+            Some(user) => {
+                auth.backend
+                    .has_perm(&user, Permission::CreateThread)
+                    .await?
+            }
             None => false,
         },
-    }))
+        prev_page: forum
+            .page
+            .prev(forum.total)
+            .map(|page| forum.page.url("/", page)),
+        next_page: forum
+            .page
+            .next(forum.total)
+            .map(|page| forum.page.url("/", page)),
+    };
+    Ok(Negotiated {
+        format,
+        html,
+        json: forum
+            .threads
+            .into_iter()
+            .map(ThreadSummary::from)
+            .collect::<Vec<_>>(),
+    })
 }
 
-async fn forum_sse(sse: ThreadSse) -> impl IntoResponse {
-    sse.into_sse(|template| {
+async fn forum_sse(timezone: ViewerTimezone, sse: ThreadSse) -> impl IntoResponse {
+    sse.into_sse(move |template| {
         Ok(PartialThreadTemplate {
             thread: template.thread,
             post: template.post,
             author: template.author,
+            tags: template.tags,
+            num_posts: template.num_posts,
+            last_reply: template.last_reply,
             sse: true,
+            timezone,
         }
         .render()?)
     })
 }
 
-pub async fn thread_post(thread: ThreadPost) -> impl IntoResponse {
-    tracing::debug!("Thread created!");
-
-    Redirect::to(&format!("/thread/{}", thread.0))
-}
-
-async fn thread(
+async fn tag(
+    format: Format,
     logged_in: LoggedIn,
-    auth: AuthSession,
-    thread: ThreadGet,
-) -> Result<impl IntoResponse> {
-    Ok(HtmlTemplate(ThreadTemplate {
+    Extension(maintenance): Extension<Maintenance>,
+    timezone: ViewerTimezone,
+    tag: TagGet,
+) -> impl IntoResponse {
+    let html = TagTemplate {
         logged_in,
-        thread: thread.thread,
-        posts: thread
-            .posts
+        maintenance: maintenance.is_active(),
+        tag: tag.tag,
+        threads: tag
+            .threads
             .iter()
             .cloned()
-            .map(|template| PartialPostTemplate {
+            .map(|template| PartialThreadTemplate {
+                thread: template.thread,
                 post: template.post,
                 author: template.author,
+                tags: template.tags,
+                num_posts: template.num_posts,
+                last_reply: template.last_reply,
                 sse: false,
+                timezone,
             })
             .join("\n"),
-        can_post: match auth.user {
-            Some(user) => auth.backend.has_perm(&user, Permission::Post).await?,
+    };
+    Negotiated {
+        format,
+        html,
+        json: tag
+            .threads
+            .into_iter()
+            .map(ThreadSummary::from)
+            .collect::<Vec<_>>(),
+    }
+}
+
+pub async fn thread_post(
+    HxBoosted(boosted): HxBoosted,
+    logged_in: LoggedIn,
+    Extension(maintenance): Extension<Maintenance>,
+    thread: ThreadPost,
+) -> impl IntoResponse {
+    match thread {
+        ThreadPost::Success(thread) => {
+            tracing::debug!("Thread created!");
+            METRICS.threads_created.inc();
+
+            Redirect::to(&url(&thread.path())).into_response()
+        }
+        ThreadPost::Failure(error) => submission_failure(
+            StatusCode::BAD_REQUEST,
+            boosted,
+            logged_in,
+            &maintenance,
+            error,
+        ),
+        ThreadPost::TooNew(error) => submission_failure(
+            StatusCode::FORBIDDEN,
+            boosted,
+            logged_in,
+            &maintenance,
+            error,
+        ),
+        // The first copy of the submission answers with the new thread.
+        ThreadPost::InFlight if boosted => StatusCode::ACCEPTED.into_response(),
+        ThreadPost::InFlight => Redirect::to(&url("/")).into_response(),
+    }
+}
+
+async fn forum_feed(feed: ForumFeedGet) -> Result<Response> {
+    let entries = feed
+        .threads
+        .into_iter()
+        .map(|template| FeedEntry {
+            link: absolute_url(&template.thread.path()),
+            title: template.thread.title,
+            author: template.author.username,
+            updated: template.post.posted_at(),
+            content: template.post.body,
+        })
+        .collect();
+    FeedTemplate::new("Lunachat".into(), "/", "/feed.xml", entries).into_atom()
+}
+
+async fn draft_get(draft: DraftGet) -> impl IntoResponse {
+    match draft.0 {
+        // Escaped so htmx swaps it into the textarea as text rather than markup.
+        Some(body) => Html(ammonia::clean_text(&body)).into_response(),
+        None => StatusCode::NO_CONTENT.into_response(),
+    }
+}
+
+async fn draft_put(_draft: DraftPut) -> impl IntoResponse {
+    StatusCode::NO_CONTENT
+}
+
+async fn thread(
+    format: Format,
+    logged_in: LoggedIn,
+    Extension(maintenance): Extension<Maintenance>,
+    auth: AuthSession,
+    ShowSignatures(show_signatures): ShowSignatures,
+    timezone: ViewerTimezone,
+    Extension(render_cache): Extension<RenderCache>,
+    uri: Uri,
+    thread: ThreadGet,
+) -> Result<Response> {
+    let ThreadGet::Success(thread) = thread else {
+        return Ok(not_found(logged_in, Extension(maintenance), uri)
+            .await
+            .into_response());
+    };
+    if format == Format::Html && !thread.is_canonical() {
+        return Ok((
+            StatusCode::MOVED_PERMANENTLY,
+            [(LOCATION, url(&thread.thread.path()))],
+        )
+            .into_response());
+    }
+
+    let can_moderate = match &auth.user {
+        Some(user) => auth.backend.has_perm(user, Permission::Moderate).await?,
+        None => false,
+    };
+    let (posts, loaded) = match thread.posts {
+        ThreadPosts::Cached(html) => (html, None),
+        ThreadPosts::Loaded { posts, version } => {
+            let can_accept = can_moderate
+                || auth.user.as_ref().is_some_and(|user| {
+                    posts
+                        .first()
+                        .is_some_and(|root| root.post.author_id == user.id)
+                });
+            let html = posts
+                .iter()
+                .cloned()
+                .enumerate()
+                .map(|(index, template)| PartialPostTemplate {
+                    accepted: thread.thread.accepted_answer == Some(template.post.id),
+                    // The root post is the question, not an answer.
+                    can_accept: can_accept && index > 0,
+                    thread_id: thread.thread.id,
+                    post: template.post,
+                    author: template.author,
+                    sse: false,
+                    can_moderate,
+                    show_signature: show_signatures,
+                    timezone,
+                })
+                .join("\n");
+            if let Some(version) = version {
+                render_cache.insert(
+                    (thread.thread.id, show_signatures, timezone),
+                    version,
+                    html.clone(),
+                    posts.iter().map(|template| template.author.id),
+                );
+            }
+            (html, Some(posts))
+        }
+    };
+    let html = ThreadTemplate {
+        logged_in,
+        maintenance: maintenance.is_active(),
+        thread: thread.thread.clone(),
+        posts,
+        can_reply: match auth.user {
+            Some(user) => auth.backend.has_perm(&user, Permission::Reply).await?,
             None => false,
         },
-    }))
+        can_moderate,
+        show_signatures,
+    };
+    Ok(Negotiated {
+        format,
+        html,
+        // Cached posts are only ever used for HTML.
+        json: loaded.map(|posts| ThreadWithPosts {
+            thread: thread.thread,
+            posts: posts.into_iter().map(PostWithAuthor::from).collect(),
+        }),
+    }
+    .into_response())
+}
+
+async fn thread_feed(feed: ThreadFeedGet) -> Result<Response> {
+    let ThreadFeedGet::Success { thread, posts } = feed else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    let thread_path = thread.path();
+    let entries = posts
+        .into_iter()
+        .map(|template| FeedEntry {
+            link: absolute_url(&format!("{thread_path}#post_{}", template.post.id)),
+            title: format!("Reply by {}", template.author.username),
+            author: template.author.username,
+            updated: template.post.posted_at(),
+            content: template.post.body,
+        })
+        .collect();
+    FeedTemplate::new(
+        thread.title,
+        &thread_path,
+        &format!("/thread/{}/feed.xml", thread.id),
+        entries,
+    )
+    .into_atom()
 }
 
-async fn thread_sse(sse: PostSse) -> impl IntoResponse {
-    sse.into_sse(|template| {
+async fn thread_sse(
+    ShowSignatures(show_signatures): ShowSignatures,
+    timezone: ViewerTimezone,
+    sse: PostSse,
+) -> impl IntoResponse {
+    sse.into_sse(move |template| {
         Ok(PartialPostTemplate {
+            thread_id: template.post.thread_id,
             post: template.post,
             author: template.author,
             sse: true,
+            can_moderate: false,
+            accepted: false,
+            can_accept: false,
+            show_signature: show_signatures,
+            timezone,
         }
         .render()?)
     })
 }
 
-pub async fn post_post(HxBoosted(boosted): HxBoosted, post: PostPost) -> impl IntoResponse {
-    tracing::debug!("Post created!");
+/// A single post's fragment, or the post as JSON.
+async fn post_fragment(
+    format: Format,
+    logged_in: LoggedIn,
+    maintenance: Extension<Maintenance>,
+    uri: Uri,
+    auth: AuthSession,
+    ShowSignatures(show_signatures): ShowSignatures,
+    timezone: ViewerTimezone,
+    post: PostGet,
+) -> Result<Response> {
+    let PostGet::Success { thread, post } = post else {
+        return Ok(not_found(logged_in, maintenance, uri).await.into_response());
+    };
+    let can_moderate = match &auth.user {
+        Some(user) => auth.backend.has_perm(user, Permission::Moderate).await?,
+        None => false,
+    };
+    Ok(Negotiated {
+        format,
+        html: PartialPostTemplate {
+            accepted: thread.accepted_answer == Some(post.post.id),
+            can_accept: false,
+            thread_id: thread.id,
+            post: post.post.clone(),
+            author: post.author.clone(),
+            sse: false,
+            can_moderate,
+            show_signature: show_signatures,
+            timezone,
+        },
+        json: PostWithAuthor::from(post),
+    }
+    .into_response())
+}
+
+pub async fn post_post(
+    HxBoosted(boosted): HxBoosted,
+    logged_in: LoggedIn,
+    Extension(maintenance): Extension<Maintenance>,
+    uri: Uri,
+    post: PostPost,
+) -> impl IntoResponse {
+    match post {
+        PostPost::Success(_, thread_id) => {
+            tracing::debug!("Post created!");
+            METRICS.posts_created.inc();
+
+            if boosted {
+                ().into_response() // Handled by SSE
+            } else {
+                Redirect::to(&url(&format!("/thread/{thread_id}"))).into_response()
+            }
+        }
+        PostPost::Failure(error) => submission_failure(
+            StatusCode::BAD_REQUEST,
+            boosted,
+            logged_in,
+            &maintenance,
+            error,
+        ),
+        PostPost::TooNew(error) => submission_failure(
+            StatusCode::FORBIDDEN,
+            boosted,
+            logged_in,
+            &maintenance,
+            error,
+        ),
+        PostPost::ThreadFull(error) => submission_failure(
+            StatusCode::CONFLICT,
+            boosted,
+            logged_in,
+            &maintenance,
+            error,
+        ),
+        PostPost::InFlight(_) if boosted => ().into_response(), // Handled by SSE
+        PostPost::InFlight(thread_id) => {
+            Redirect::to(&url(&format!("/thread/{thread_id}"))).into_response()
+        }
+        PostPost::NotFound => not_found(logged_in, Extension(maintenance), uri)
+            .await
+            .into_response(),
+    }
+}
 
+/// Boosted forms keep what was typed, so they only need the message. Anything else gets
+/// the compose form back with the submission filled in.
+fn submission_failure(
+    status: StatusCode,
+    boosted: bool,
+    logged_in: LoggedIn,
+    maintenance: &Maintenance,
+    error: SubmissionError,
+) -> Response {
     if boosted {
-        ().into_response() // Handled by SSE
+        (
+            status,
+            HtmlTemplate(PartialSubmissionErrorTemplate { error }),
+        )
+            .into_response()
     } else {
-        Redirect::to(&format!("/thread/{}", post.1)).into_response()
+        (
+            status,
+            HtmlTemplate(ComposeTemplate {
+                logged_in,
+                maintenance: maintenance.is_active(),
+                error,
+            }),
+        )
+            .into_response()
     }
 }
 
-async fn user(logged_in: LoggedIn, user: UserGet) -> impl IntoResponse {
-    HtmlTemplate(UserTemplate {
-        logged_in,
-        user: user.user,
+async fn preview_post(preview: PreviewPost) -> impl IntoResponse {
+    match preview {
+        PreviewPost::Success(body) => Html(body).into_response(),
+        PreviewPost::Failure { error } => (StatusCode::BAD_REQUEST, error).into_response(),
+    }
+}
+
+async fn accept_answer(accept: AcceptAnswerPost) -> impl IntoResponse {
+    match accept {
+        AcceptAnswerPost::Success(thread) => {
+            tracing::debug!(
+                "Thread {} accepted answer: {:?}",
+                thread.id,
+                thread.accepted_answer
+            );
+            let path = url(&thread.path());
+            // htmx follows redirects itself, so tell it to navigate instead.
+            ([("HX-Redirect", path.clone())], Redirect::to(&path)).into_response()
+        }
+        AcceptAnswerPost::Failure { error } => (StatusCode::BAD_REQUEST, error).into_response(),
+        AcceptAnswerPost::Forbidden => (
+            StatusCode::FORBIDDEN,
+            "Only the thread's author can accept an answer",
+        )
+            .into_response(),
+    }
+}
+
+async fn thread_pin(
+    Extension(webhooks): Extension<Webhooks>,
+    pin: ThreadPinPost,
+) -> impl IntoResponse {
+    tracing::debug!("Thread {} pinned: {}", pin.0.id, pin.0.pinned);
+    webhooks.notify(WebhookEvent::Moderation(ModerationAction::ThreadPinned {
+        thread_id: pin.0.id,
+        pinned: pin.0.pinned,
+    }));
+
+    Redirect::to(&url(&pin.0.path()))
+}
+
+async fn maintenance(
+    Extension(webhooks): Extension<Webhooks>,
+    maintenance: MaintenancePost,
+) -> impl IntoResponse {
+    tracing::info!("Maintenance mode: {}", maintenance.0);
+    webhooks.notify(WebhookEvent::Moderation(ModerationAction::Maintenance {
+        enabled: maintenance.0,
+    }));
+
+    Redirect::to(&url("/"))
+}
+
+async fn post_approve(
+    Extension(webhooks): Extension<Webhooks>,
+    approve: PostApprovePost,
+) -> impl IntoResponse {
+    tracing::debug!("Post {} approved", approve.0);
+    webhooks.notify(WebhookEvent::Moderation(ModerationAction::PostApproved {
+        post_id: approve.0,
+        thread_id: approve.1,
+    }));
+
+    Redirect::to(&url(&format!("/thread/{}", approve.1)))
+}
+
+async fn user(
+    format: Format,
+    logged_in: LoggedIn,
+    Extension(maintenance): Extension<Maintenance>,
+    user: UserGet,
+) -> impl IntoResponse {
+    Negotiated {
+        format,
+        json: PublicUser::from(user.user.clone()),
+        html: UserTemplate {
+            is_self: matches!(&logged_in, LoggedIn::Yes { user: viewer } if viewer.id == user.user.id),
+            logged_in,
+            maintenance: maintenance.is_active(),
+            user: user.user,
+        },
+    }
+}
+
+async fn signature_post(signature: SignaturePost) -> impl IntoResponse {
+    match signature {
+        SignaturePost::Success(user) => {
+            Redirect::to(&url(&format!("/user/{}", user.id))).into_response()
+        }
+        SignaturePost::Failure { error } => (StatusCode::BAD_REQUEST, error).into_response(),
+    }
+}
+
+async fn timezone_post(timezone: TimezonePost) -> impl IntoResponse {
+    match timezone {
+        TimezonePost::Success(user) => {
+            Redirect::to(&url(&format!("/user/{}", user.id))).into_response()
+        }
+        TimezonePost::Failure { error } => (StatusCode::BAD_REQUEST, error).into_response(),
+    }
+}
+
+async fn user_delete(delete: UserDeletePost) -> impl IntoResponse {
+    match delete {
+        UserDeletePost::Success(user_id) => {
+            tracing::debug!("User {user_id} deleted their account");
+            Redirect::to(&url("/")).into_response()
+        }
+        UserDeletePost::Failure { error } => (StatusCode::BAD_REQUEST, error).into_response(),
+    }
+}
+
+async fn user_delete_admin(
+    Extension(webhooks): Extension<Webhooks>,
+    delete: UserDeleteAdminPost,
+) -> impl IntoResponse {
+    tracing::debug!("User {} deleted by a moderator", delete.0);
+    webhooks.notify(WebhookEvent::Moderation(ModerationAction::UserDeleted {
+        user_id: delete.0,
+    }));
+
+    Redirect::to(&url("/"))
+}
+
+async fn api_threads(threads: ThreadsPageGet) -> Result<Response> {
+    match threads {
+        ThreadsPageGet::Success {
+            threads,
+            page,
+            total,
+        } => Ok((
+            [
+                (LINK, page.link_header("/api/threads", total)?),
+                (
+                    HeaderName::from_static("x-total-count"),
+                    HeaderValue::from(total),
+                ),
+            ],
+            Json(
+                threads
+                    .into_iter()
+                    .map(ThreadSummary::from)
+                    .collect::<Vec<_>>(),
+            ),
+        )
+            .into_response()),
+        ThreadsPageGet::Failure { error } => Ok(ApiError::response(StatusCode::BAD_REQUEST, error)),
+    }
+}
+
+async fn api_formatting(Extension(sanitizer): Extension<Sanitizer>) -> impl IntoResponse {
+    Json(sanitizer.formatting())
+}
+
+async fn formatting(Extension(sanitizer): Extension<Sanitizer>) -> impl IntoResponse {
+    HtmlTemplate(PartialFormattingTemplate {
+        formatting: sanitizer.formatting(),
+    })
+}
+
+async fn user_search(search: UserSearchGet) -> impl IntoResponse {
+    Json(
+        search
+            .users
+            .into_iter()
+            .map(PublicUser::from)
+            .collect::<Vec<_>>(),
+    )
+}
+
+async fn whoami(auth: AuthSession) -> Result<Response> {
+    let Some(user) = auth.user else {
+        return Ok(ApiError::unauthorized());
+    };
+    let permissions = auth
+        .backend
+        .get_user_permissions(&user)
+        .await?
+        .into_iter()
+        .collect();
+    Ok(Json(Whoami {
+        user: user.into(),
+        permissions,
     })
+    .into_response())
 }
 
-async fn login(login: LoginGet) -> impl IntoResponse {
+async fn login(
+    Extension(maintenance): Extension<Maintenance>,
+    login: LoginGet,
+) -> impl IntoResponse {
     HtmlTemplate(LoginTemplate {
+        maintenance: maintenance.is_active(),
         login_error: login.error,
+        field_errors: FieldErrors::default(),
         next: login.next,
     })
 }
 
-async fn login_post(login: LoginPost) -> impl IntoResponse {
+async fn login_post(
+    Extension(maintenance): Extension<Maintenance>,
+    login: LoginPost,
+) -> impl IntoResponse {
     match login {
         LoginPost::Success { user, next } => {
             tracing::debug!("Logged in user: {:?}", user);
-            Redirect::to(next.as_ref().map_or("/", |v| v)).into_response()
+            METRICS.logins.inc();
+            Redirect::to(&next).into_response()
+        }
+        LoginPost::Failure { error, next } => {
+            METRICS.login_failures.inc();
+            HtmlTemplate(LoginTemplate {
+                maintenance: maintenance.is_active(),
+                login_error: Some(error),
+                field_errors: FieldErrors::default(),
+                next,
+            })
+            .into_response()
+        }
+        LoginPost::TooManyAttempts { retry_after, next } => {
+            METRICS.login_failures.inc();
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(RETRY_AFTER, retry_after.as_secs().max(1).to_string())],
+                HtmlTemplate(LoginTemplate {
+                    maintenance: maintenance.is_active(),
+                    login_error: Some("Too many failed logins, try again later".into()),
+                    field_errors: FieldErrors::default(),
+                    next,
+                }),
+            )
+                .into_response()
         }
-        LoginPost::Failure { error, next } => HtmlTemplate(LoginTemplate {
-            login_error: Some(error),
-            next,
-        })
-        .into_response(),
     }
 }
 
 pub async fn logout_post(_logout: LogoutPost) -> impl IntoResponse {
-    Redirect::to("/").into_response()
+    Redirect::to(&url("/")).into_response()
 }
 
-async fn register_post(register: RegisterPost) -> impl IntoResponse {
+async fn register_post(
+    Extension(maintenance): Extension<Maintenance>,
+    register: RegisterPost,
+) -> impl IntoResponse {
     match register {
         RegisterPost::Success { user, next } => {
             tracing::debug!("Registered user: {:?}", user);
-            Redirect::to(next.as_ref().map_or("/", |v| v)).into_response()
+            Redirect::to(&next).into_response()
         }
-        RegisterPost::Failure { error, next } => HtmlTemplate(LoginTemplate {
-            login_error: Some(error),
+        RegisterPost::Failure { errors, next } => HtmlTemplate(LoginTemplate {
+            maintenance: maintenance.is_active(),
+            login_error: None,
+            field_errors: errors,
             next,
         })
         .into_response(),
     }
 }
 
+async fn metrics(
+    Extension(token): Extension<MetricsToken>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    if !token.authorizes(&headers) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+        METRICS.render(),
+    )
+        .into_response()
+}
+
+async fn favicon() -> impl IntoResponse {
+    match tokio::fs::read("static/favicon.ico").await {
+        Ok(icon) => ([(CONTENT_TYPE, "image/x-icon")], icon).into_response(),
+        Err(_) => StatusCode::NO_CONTENT.into_response(),
+    }
+}
+
+async fn robots(Extension(policy): Extension<RobotsPolicy>) -> impl IntoResponse {
+    (
+        [(CONTENT_TYPE, "text/plain; charset=utf-8")],
+        policy.render(),
+    )
+}
+
+async fn not_found(
+    logged_in: LoggedIn,
+    Extension(maintenance): Extension<Maintenance>,
+    uri: Uri,
+) -> impl IntoResponse {
+    if uri.path().starts_with("/api/") {
+        return ApiError::response(StatusCode::NOT_FOUND, "Not found");
+    }
+
+    (
+        StatusCode::NOT_FOUND,
+        HtmlTemplate(NotFoundTemplate {
+            logged_in,
+            maintenance: maintenance.is_active(),
+            path: uri.path().to_string(),
+        }),
+    )
+        .into_response()
+}
+
+/// Renders every template once with placeholder data, so a template that fails to render
+/// stops the deploy instead of turning the first request that hits it into a 500.
+fn self_check() -> Result<()> {
+    let user = user::Model {
+        id: Default::default(),
+        username: "self-check".into(),
+        password: String::new(),
+        avatar: None,
+        moderator: false,
+        signature: Some("Self check".into()),
+        created_at: Some(Default::default()),
+        timezone: Some("Europe/Berlin".into()),
+    };
+    let thread = thread::Model {
+        id: Default::default(),
+        title: "Self check".into(),
+        slug: "self-check".into(),
+        pinned: true,
+        views: 1,
+        accepted_answer: None,
+    };
+    let post = post::Model {
+        id: Default::default(),
+        body: "Self check".into(),
+        created_at: Default::default(),
+        author_id: user.id,
+        thread_id: thread.id,
+        approved: false,
+        publish_at: Some(Default::default()),
+        published: false,
+    };
+    let logged_in = || LoggedIn::Yes { user: user.clone() };
+    let submission_error = SubmissionError {
+        error: "Self check".into(),
+        field_errors: FieldErrors(vec![FieldError {
+            field: "body",
+            message: "Self check".into(),
+        }]),
+        thread_id: Some(thread.id),
+        title: "Self check".into(),
+        tags: "self-check".into(),
+        body: "Self check".into(),
+    };
+
+    let partial_thread = PartialThreadTemplate {
+        thread: thread.clone(),
+        post: post.clone(),
+        author: user.clone(),
+        tags: vec!["self-check".into()],
+        num_posts: 2,
+        last_reply: Some((post.clone(), user.clone())),
+        sse: true,
+        timezone: ViewerTimezone::of(Some(&user)),
+    }
+    .render();
+    let partial_post = PartialPostTemplate {
+        post: post.clone(),
+        author: user.clone(),
+        thread_id: thread.id,
+        sse: true,
+        can_moderate: true,
+        accepted: true,
+        can_accept: true,
+        show_signature: true,
+        timezone: ViewerTimezone::of(Some(&user)),
+    }
+    .render();
+    let checks = [
+        (
+            "forum",
+            ForumTemplate {
+                logged_in: logged_in(),
+                maintenance: true,
+                threads: String::new(),
+                can_create_thread: true,
+                prev_page: Some("/".into()),
+                next_page: Some("/".into()),
+            }
+            .render(),
+        ),
+        (
+            "thread",
+            ThreadTemplate {
+                logged_in: LoggedIn::No {
+                    url: "/".into(),
+                    login_error: Some("Self check".into()),
+                },
+                maintenance: true,
+                thread: thread.clone(),
+                posts: String::new(),
+                can_reply: true,
+                can_moderate: true,
+                show_signatures: true,
+            }
+            .render(),
+        ),
+        (
+            "tag",
+            TagTemplate {
+                logged_in: logged_in(),
+                maintenance: true,
+                tag: "self-check".into(),
+                threads: String::new(),
+            }
+            .render(),
+        ),
+        (
+            "compose",
+            ComposeTemplate {
+                logged_in: logged_in(),
+                maintenance: true,
+                error: submission_error.clone(),
+            }
+            .render(),
+        ),
+        (
+            "login",
+            LoginTemplate {
+                maintenance: true,
+                login_error: Some("Self check".into()),
+                field_errors: FieldErrors(vec![FieldError {
+                    field: "username",
+                    message: "Self check".into(),
+                }]),
+                next: Some("/".into()),
+            }
+            .render(),
+        ),
+        (
+            "user",
+            UserTemplate {
+                logged_in: logged_in(),
+                maintenance: true,
+                user: user.clone(),
+                is_self: true,
+            }
+            .render(),
+        ),
+        (
+            "not_found",
+            NotFoundTemplate {
+                logged_in: logged_in(),
+                maintenance: true,
+                path: "/".into(),
+            }
+            .render(),
+        ),
+        (
+            "feed",
+            FeedTemplate::new(
+                thread.title.clone(),
+                &thread.path(),
+                "/feed.xml",
+                vec![FeedEntry {
+                    link: absolute_url(&thread.path()),
+                    title: thread.title.clone(),
+                    author: user.username.clone(),
+                    updated: post.posted_at(),
+                    content: post.body.clone(),
+                }],
+            )
+            .render(),
+        ),
+        ("partial/thread", partial_thread),
+        (
+            "partial/submission_error",
+            PartialSubmissionErrorTemplate {
+                error: SubmissionError {
+                    thread_id: None,
+                    ..submission_error
+                },
+            }
+            .render(),
+        ),
+        ("partial/post", partial_post),
+        (
+            "partial/formatting",
+            PartialFormattingTemplate {
+                formatting: Formatting {
+                    tags: vec!["a".into()],
+                    generic_attributes: vec!["style".into()],
+                    tag_attributes: [("a".into(), vec!["href".into()])].into(),
+                    url_schemes: vec!["https".into()],
+                },
+            }
+            .render(),
+        ),
+    ];
+    for (name, rendered) in checks {
+        rendered.map_err(|err| anyhow!("Template {name} failed to render: {err}"))?;
+    }
+
+    tracing::debug!("All templates rendered");
+    Ok(())
+}
+
 enum LoggedIn {
     Yes {
         user: user::Model,
@@ -211,7 +1093,7 @@ where
             Ok(LoggedIn::Yes { user })
         } else {
             Ok(LoggedIn::No {
-                url: parts.extract::<Uri>().await?.to_string(),
+                url: parts.extract::<OriginalUri>().await?.0.to_string(),
                 login_error: None,
             })
         }
@@ -222,23 +1104,60 @@ where
 #[template(path = "forum.html.jinja")]
 struct ForumTemplate {
     logged_in: LoggedIn,
+    maintenance: bool,
     threads: String,
-    can_post: bool,
+    can_create_thread: bool,
+    prev_page: Option<String>,
+    next_page: Option<String>,
 }
 
 #[derive(Template)]
 #[template(path = "thread.html.jinja")]
 struct ThreadTemplate {
     logged_in: LoggedIn,
+    maintenance: bool,
     thread: thread::Model,
     posts: String,
-    can_post: bool,
+    can_reply: bool,
+    can_moderate: bool,
+    show_signatures: bool,
+}
+
+#[derive(Template)]
+#[template(path = "tag.html.jinja")]
+struct TagTemplate {
+    logged_in: LoggedIn,
+    maintenance: bool,
+    tag: String,
+    threads: String,
+}
+
+#[derive(Template)]
+#[template(path = "compose.html.jinja")]
+struct ComposeTemplate {
+    logged_in: LoggedIn,
+    maintenance: bool,
+    error: SubmissionError,
+}
+
+#[derive(Template)]
+#[template(path = "partial/formatting.html.jinja")]
+struct PartialFormattingTemplate {
+    formatting: Formatting,
+}
+
+#[derive(Template)]
+#[template(path = "partial/submission_error.html.jinja")]
+struct PartialSubmissionErrorTemplate {
+    error: SubmissionError,
 }
 
 #[derive(Template)]
 #[template(path = "login.html.jinja")]
 struct LoginTemplate {
+    maintenance: bool,
     login_error: Option<String>,
+    field_errors: FieldErrors,
     next: Option<String>,
 }
 
@@ -246,7 +1165,17 @@ struct LoginTemplate {
 #[template(path = "user.html.jinja")]
 struct UserTemplate {
     logged_in: LoggedIn,
+    maintenance: bool,
     user: user::Model,
+    is_self: bool,
+}
+
+#[derive(Template)]
+#[template(path = "not_found.html.jinja")]
+struct NotFoundTemplate {
+    logged_in: LoggedIn,
+    maintenance: bool,
+    path: String,
 }
 
 #[derive(Template)]
@@ -255,7 +1184,53 @@ struct PartialThreadTemplate {
     thread: thread::Model,
     post: post::Model,
     author: user::Model,
+    tags: Vec<String>,
+    num_posts: u64,
+    last_reply: Option<(post::Model, user::Model)>,
     sse: bool,
+    timezone: ViewerTimezone,
+}
+
+#[derive(Template)]
+#[template(path = "feed.xml.jinja")]
+struct FeedTemplate {
+    title: String,
+    link: String,
+    link_self: String,
+    updated: DateTime<Utc>,
+    entries: Vec<FeedEntry>,
+}
+
+struct FeedEntry {
+    link: String,
+    title: String,
+    author: String,
+    updated: DateTime<Utc>,
+    content: String,
+}
+
+impl FeedTemplate {
+    fn new(title: String, path: &str, path_self: &str, entries: Vec<FeedEntry>) -> Self {
+        Self {
+            title,
+            link: absolute_url(path),
+            link_self: absolute_url(path_self),
+            updated: entries
+                .iter()
+                .map(|entry| entry.updated)
+                .max()
+                .unwrap_or_default(),
+            entries,
+        }
+    }
+
+    fn into_atom(self) -> Result<Response> {
+        Ok((
+            [(CONTENT_TYPE, "application/atom+xml; charset=utf-8")],
+            self.render()?,
+        )
+            .into_response())
+    }
 }
 
 #[derive(Template)]
@@ -263,5 +1238,13 @@ struct PartialThreadTemplate {
 struct PartialPostTemplate {
     post: post::Model,
     author: user::Model,
+    thread_id: thread::Id,
     sse: bool,
+    can_moderate: bool,
+    /// Whether this is the thread's accepted answer.
+    accepted: bool,
+    /// Whether the viewer may mark this post as the accepted answer.
+    can_accept: bool,
+    show_signature: bool,
+    timezone: ViewerTimezone,
 }
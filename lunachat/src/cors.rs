@@ -0,0 +1,92 @@
+use std::env;
+
+use axum::http::header::CONTENT_TYPE;
+use axum::http::{HeaderValue, Method};
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+
+use crate::prelude::*;
+
+/// Builds the CORS policy for `/api/*` from `LUNACHAT_CORS_ORIGINS`.
+///
+/// The variable is a comma-separated allowlist of origins, or `*` to allow any origin.
+/// Credentials (the session cookie) are only allowed for explicitly listed origins.
+pub fn layer() -> Result<CorsLayer> {
+    from_origins(&env::var("LUNACHAT_CORS_ORIGINS").unwrap_or_default())
+}
+
+fn from_origins(origins: &str) -> Result<CorsLayer> {
+    let methods = [Method::GET, Method::POST, Method::PUT, Method::DELETE];
+
+    if origins.trim() == "*" {
+        return Ok(CorsLayer::new()
+            .allow_origin(Any)
+            .allow_methods(methods)
+            .allow_headers([CONTENT_TYPE]));
+    }
+
+    let origins = origins
+        .split(',')
+        .map(str::trim)
+        .filter(|origin| !origin.is_empty())
+        .map(HeaderValue::from_str)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(CorsLayer::new()
+        .allow_origin(AllowOrigin::list(origins))
+        .allow_credentials(true)
+        .allow_methods(methods)
+        .allow_headers([CONTENT_TYPE]))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::Router;
+    use axum::body::Body;
+    use axum::http::header::{
+        ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_ORIGIN, ORIGIN,
+    };
+    use axum::http::{HeaderMap, Request};
+    use axum::routing::get;
+    use tower::ServiceExt as _;
+
+    use super::*;
+
+    async fn cors_headers(origins: &str, origin: &str) -> HeaderMap {
+        let app = Router::new()
+            .route("/api/threads", get(|| async { "[]" }))
+            .layer(from_origins(origins).unwrap());
+        let request = Request::get("/api/threads")
+            .header(ORIGIN, origin)
+            .body(Body::empty())
+            .unwrap();
+        app.oneshot(request).await.unwrap().headers().clone()
+    }
+
+    #[tokio::test]
+    async fn wildcard_allows_any_origin_without_credentials() {
+        let headers = cors_headers("*", "https://anywhere.example").await;
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        assert!(!headers.contains_key(ACCESS_CONTROL_ALLOW_CREDENTIALS));
+    }
+
+    #[tokio::test]
+    async fn listed_origins_get_credentials() {
+        let origins = "https://a.example, https://b.example";
+        let headers = cors_headers(origins, "https://b.example").await;
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_ORIGIN], "https://b.example");
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+    }
+
+    #[tokio::test]
+    async fn unlisted_origins_are_not_allowed() {
+        let headers = cors_headers("https://a.example", "https://evil.example").await;
+        assert!(!headers.contains_key(ACCESS_CONTROL_ALLOW_ORIGIN));
+        let headers = cors_headers("", "https://a.example").await;
+        assert!(!headers.contains_key(ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+
+    #[test]
+    fn invalid_origins_are_refused() {
+        assert!(from_origins("https://a.example,bad\norigin").is_err());
+    }
+}
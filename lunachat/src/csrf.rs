@@ -0,0 +1,185 @@
+use std::env;
+
+use axum::extract::{Request, State};
+use axum::http::header::{ORIGIN, REFERER};
+use axum::http::{HeaderMap, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use url::Url;
+
+use crate::prelude::*;
+
+/// Refuses state-changing requests that a browser says came from another site, judged by
+/// `Origin` and, where a browser leaves that out, `Referer`.
+///
+/// Enabled by `LUNACHAT_CHECK_ORIGIN`, which defaults to whether `LUNACHAT_BASE_URL` is
+/// set, since the origin requests are compared against comes from there.
+///
+/// A request with neither header is let through. Browsers send `Origin` on cross-site
+/// POSTs, so a forged form always carries one, but some privacy extensions and proxies
+/// strip both from legitimate requests too. `/api/*` is left to its CORS policy.
+#[derive(Clone)]
+pub struct OriginCheck {
+    /// `None` when the check is off.
+    origin: Option<String>,
+}
+
+impl OriginCheck {
+    pub fn from_env() -> Result<Self> {
+        let enabled = match env::var("LUNACHAT_CHECK_ORIGIN") {
+            Ok(enabled) => enabled.parse()?,
+            Err(_) => env::var("LUNACHAT_BASE_URL").is_ok(),
+        };
+        let origin = if enabled {
+            Some(
+                Url::parse(&crate::absolute_url("/"))?
+                    .origin()
+                    .ascii_serialization(),
+            )
+        } else {
+            None
+        };
+        Ok(Self { origin })
+    }
+
+    /// Whether the request says where it came from, and that isn't this site.
+    fn is_cross_site(&self, headers: &HeaderMap) -> bool {
+        let Some(expected) = &self.origin else {
+            return false;
+        };
+        let claimed = match headers.get(ORIGIN) {
+            // An opaque origin, as sent from sandboxed frames, can't be this site.
+            Some(origin) => origin.to_str().ok().map(str::to_string),
+            None => match headers.get(REFERER) {
+                Some(referer) => Some(
+                    referer
+                        .to_str()
+                        .ok()
+                        .and_then(|referer| Url::parse(referer).ok())
+                        .map(|referer| referer.origin().ascii_serialization())
+                        .unwrap_or_default(),
+                ),
+                None => return false,
+            },
+        };
+        claimed.as_deref() != Some(expected.as_str())
+    }
+}
+
+/// Answers cross-site state-changing requests with 403. Reads and streams are never
+/// checked.
+pub async fn check_origin(State(check): State<OriginCheck>, req: Request, next: Next) -> Response {
+    if matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS)
+        || req.uri().path().starts_with(&crate::url("/api/"))
+        || !check.is_cross_site(req.headers())
+    {
+        return next.run(req).await;
+    }
+
+    tracing::debug!("Refused cross-site {} {}", req.method(), req.uri());
+    (StatusCode::FORBIDDEN, "Cross-site request refused").into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::Router;
+    use axum::body::Body;
+    use axum::http::Request;
+    use axum::routing::post;
+    use tower::ServiceExt as _;
+
+    use super::*;
+
+    const SITE: &str = "https://forum.example";
+
+    async fn status(path: &str, headers: &[(&str, &str)]) -> StatusCode {
+        let check = OriginCheck {
+            origin: Some(SITE.into()),
+        };
+        let app = Router::new()
+            .route("/thread/{id}", post(|| async { "" }))
+            .route("/api/threads", post(|| async { "" }))
+            .layer(axum::middleware::from_fn_with_state(check, check_origin));
+        let mut request = Request::post(path);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        app.oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn same_site_requests_are_let_through() {
+        assert_eq!(
+            status("/thread/1", &[("origin", SITE)]).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(
+                "/thread/1",
+                &[("referer", "https://forum.example/thread/1")]
+            )
+            .await,
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn cross_site_requests_are_refused() {
+        assert_eq!(
+            status("/thread/1", &[("origin", "https://evil.example")]).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status("/thread/1", &[("referer", "https://evil.example/form")]).await,
+            StatusCode::FORBIDDEN
+        );
+        // Origin wins over a Referer that happens to match.
+        assert_eq!(
+            status(
+                "/thread/1",
+                &[
+                    ("origin", "https://evil.example"),
+                    ("referer", "https://forum.example/")
+                ]
+            )
+            .await,
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[tokio::test]
+    async fn opaque_and_unparsable_sources_are_refused() {
+        assert_eq!(
+            status("/thread/1", &[("origin", "null")]).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status("/thread/1", &[("referer", "not a url")]).await,
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[tokio::test]
+    async fn requests_without_either_header_are_let_through() {
+        assert_eq!(status("/thread/1", &[]).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn api_is_left_to_cors() {
+        assert_eq!(
+            status("/api/threads", &[("origin", "https://evil.example")]).await,
+            StatusCode::OK
+        );
+    }
+
+    #[test]
+    fn disabled_check_lets_everything_through() {
+        let check = OriginCheck { origin: None };
+        let mut headers = HeaderMap::new();
+        headers.insert(ORIGIN, "https://evil.example".parse().unwrap());
+        assert!(!check.is_cross_site(&headers));
+    }
+}
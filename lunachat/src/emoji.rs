@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+
+use lazy_static::lazy_static;
+
+use crate::html;
+
+/// Shortcodes expanded by [`expand`], named as on GitHub and Slack.
+const SHORTCODES: &[(&str, &str)] = &[
+    ("smile", "😄"),
+    ("smiley", "😃"),
+    ("grin", "😁"),
+    ("laughing", "😆"),
+    ("joy", "😂"),
+    ("rofl", "🤣"),
+    ("wink", "😉"),
+    ("blush", "😊"),
+    ("slightly_smiling_face", "🙂"),
+    ("upside_down_face", "🙃"),
+    ("heart_eyes", "😍"),
+    ("kissing_heart", "😘"),
+    ("stuck_out_tongue", "😛"),
+    ("sunglasses", "😎"),
+    ("thinking", "🤔"),
+    ("neutral_face", "😐"),
+    ("expressionless", "😑"),
+    ("unamused", "😒"),
+    ("roll_eyes", "🙄"),
+    ("grimacing", "😬"),
+    ("relieved", "😌"),
+    ("pensive", "😔"),
+    ("sleepy", "😪"),
+    ("sleeping", "😴"),
+    ("confused", "😕"),
+    ("worried", "😟"),
+    ("frowning", "😦"),
+    ("open_mouth", "😮"),
+    ("astonished", "😲"),
+    ("flushed", "😳"),
+    ("cry", "😢"),
+    ("sob", "😭"),
+    ("scream", "😱"),
+    ("angry", "😠"),
+    ("rage", "😡"),
+    ("skull", "💀"),
+    ("heart", "❤️"),
+    ("broken_heart", "💔"),
+    ("sparkles", "✨"),
+    ("star", "⭐"),
+    ("fire", "🔥"),
+    ("100", "💯"),
+    ("tada", "🎉"),
+    ("wave", "👋"),
+    ("clap", "👏"),
+    ("pray", "🙏"),
+    ("ok_hand", "👌"),
+    ("+1", "👍"),
+    ("thumbsup", "👍"),
+    ("-1", "👎"),
+    ("thumbsdown", "👎"),
+    ("eyes", "👀"),
+    ("muscle", "💪"),
+    ("check", "✔️"),
+    ("white_check_mark", "✅"),
+    ("x", "❌"),
+    ("warning", "⚠️"),
+    ("question", "❓"),
+    ("exclamation", "❗"),
+    ("bulb", "💡"),
+    ("rocket", "🚀"),
+    ("bug", "🐛"),
+    ("coffee", "☕"),
+    ("fox_face", "🦊"),
+    ("dragon", "🐉"),
+    ("crescent_moon", "🌙"),
+    ("sun_with_face", "🌞"),
+];
+
+lazy_static! {
+    static ref EMOJI: HashMap<&'static str, &'static str> = SHORTCODES.iter().copied().collect();
+}
+
+/// Elements whose text is left exactly as written.
+const VERBATIM: &[&str] = &["code", "pre"];
+
+/// Replaces known `:shortcode:`s in the text of sanitized `html` with their emoji. Unknown
+/// shortcodes, tags and attributes, and anything inside `<code>` or `<pre>` are left
+/// alone.
+pub fn expand(html: &str) -> String {
+    html::rewrite_text(html, VERBATIM, expand_text)
+}
+
+fn expand_text(text: &str, out: &mut String) {
+    let mut rest = text;
+    while let Some(start) = rest.find(':') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let code_len = after
+            .find(|char: char| !(char.is_ascii_alphanumeric() || matches!(char, '_' | '+' | '-')))
+            .unwrap_or(after.len());
+        if after[code_len..].starts_with(':')
+            && let Some(emoji) = EMOJI.get(&after[..code_len])
+        {
+            out.push_str(emoji);
+            rest = &after[code_len + 1..];
+        } else {
+            // The colon that ended this might open the next shortcode.
+            out.push(':');
+            rest = after;
+        }
+    }
+    out.push_str(rest);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_shortcodes_are_expanded() {
+        assert_eq!(expand("<p>hi :wave: :+1:</p>"), "<p>hi 👋 👍</p>");
+    }
+
+    #[test]
+    fn unknown_shortcodes_are_kept() {
+        assert_eq!(expand("<p>:nope: at 10:30</p>"), "<p>:nope: at 10:30</p>");
+    }
+
+    #[test]
+    fn colon_may_open_the_next_shortcode() {
+        assert_eq!(expand("<p>ratio:fire:</p>"), "<p>ratio🔥</p>");
+        assert_eq!(expand("<p>::smile::</p>"), "<p>:😄:</p>");
+    }
+
+    #[test]
+    fn code_and_pre_are_left_alone() {
+        assert_eq!(
+            expand("<p>:fire: <code>:fire:</code></p><pre>a :fire: b</pre>"),
+            "<p>🔥 <code>:fire:</code></p><pre>a :fire: b</pre>"
+        );
+        assert_eq!(
+            expand("<pre><code>:fire:</code> :fire:</pre>:fire:"),
+            "<pre><code>:fire:</code> :fire:</pre>🔥"
+        );
+    }
+
+    #[test]
+    fn attributes_are_left_alone() {
+        assert_eq!(
+            expand(r#"<a href="https://example.com/:fire:" title=":fire:">:fire:</a>"#),
+            r#"<a href="https://example.com/:fire:" title=":fire:">🔥</a>"#
+        );
+    }
+}
@@ -0,0 +1,46 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use sea_orm::entity::prelude::*;
+
+use crate::prelude::*;
+
+/// Autosaved text of a post that hasn't been sent yet. Drafts are private to their author
+/// and stored exactly as typed; they're only sanitized once actually posted.
+#[sea_orm::model]
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "draft")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub author_id: user::Id,
+    /// The thread being replied to, or [`new_thread`] for a draft of a new thread.
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub thread_id: thread::Id,
+    pub body: String,
+    pub updated_at: DateTimeUtc,
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+/// Thread id drafts of new threads are saved under. No real thread has it.
+pub fn new_thread() -> thread::Id {
+    thread::Id::default()
+}
+
+/// Deletes drafts untouched for longer than `ttl`, checking once an hour.
+pub fn purge_expired(db: DatabaseConnection, ttl: Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+        loop {
+            interval.tick().await;
+            let Ok(ttl) = chrono::Duration::from_std(ttl) else {
+                return;
+            };
+            match db.purge_drafts_before(Utc::now() - ttl).await {
+                Ok(0) => {}
+                Ok(purged) => tracing::debug!("Purged {purged} expired drafts"),
+                Err(err) => tracing::warn!("Failed to purge expired drafts: {err}"),
+            }
+        }
+    });
+}
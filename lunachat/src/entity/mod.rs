@@ -1,14 +1,20 @@
 use std::collections::{HashMap, HashSet};
 
+use chrono::{DateTime, Utc};
+use itertools::Itertools as _;
 use sea_orm::ActiveValue::{NotSet, Set};
+use sea_orm::sea_query::{Expr, OnConflict, Query};
 use sea_orm::{
-    ActiveModelTrait, ColumnTrait, DbErr, IntoActiveModel, ModelTrait, QueryFilter, QueryOrder,
+    ActiveModelTrait, ColumnTrait, Condition, DbErr, IntoActiveModel, PaginatorTrait, QueryFilter,
+    QueryOrder, QuerySelect, TransactionTrait,
 };
 
 use crate::prelude::*;
 
+pub mod draft;
 pub mod post;
 pub mod thread;
+pub mod thread_tag;
 pub mod user;
 
 #[derive(Clone)]
@@ -21,6 +27,12 @@ pub enum BroadcastEvent<T> {
 pub trait DatabaseConnectionExt {
     fn get_user(&self, id: user::Id) -> impl Future<Output = Result<user::Model>>;
     fn find_user(&self, id: user::Id) -> impl Future<Output = Result<Option<user::Model>, DbErr>>;
+    /// Fetches each distinct user in `ids` once. Users that don't exist are left out.
+    fn find_users(
+        &self,
+        ids: impl IntoIterator<Item = user::Id>,
+    ) -> impl Future<Output = Result<HashMap<user::Id, user::Model>, DbErr>>;
+    fn get_author(&self, id: user::Id) -> impl Future<Output = Result<user::Model, DbErr>>;
     fn get_user_by_username(
         &self,
         username: impl Into<String>,
@@ -29,13 +41,131 @@ pub trait DatabaseConnectionExt {
         &self,
         username: impl Into<String>,
     ) -> impl Future<Output = Result<Option<user::Model>, DbErr>>;
+    fn find_users_by_usernames(
+        &self,
+        usernames: impl IntoIterator<Item = String>,
+    ) -> impl Future<Output = Result<HashMap<String, user::Model>, DbErr>>;
+    fn search_users_by_prefix(
+        &self,
+        prefix: &str,
+        limit: u64,
+    ) -> impl Future<Output = Result<Vec<user::Model>, DbErr>>;
     fn insert_user(&self, user: user::NewModel) -> impl Future<Output = Result<user::Model>>;
+    fn set_signature(
+        &self,
+        id: user::Id,
+        signature: Option<String>,
+    ) -> impl Future<Output = Result<user::Model>>;
+    fn set_timezone(
+        &self,
+        id: user::Id,
+        timezone: Option<String>,
+    ) -> impl Future<Output = Result<user::Model>>;
+    fn delete_user(&self, id: user::Id, mode: user::DeleteMode)
+    -> impl Future<Output = Result<()>>;
 
     fn get_post(&self, id: post::Id) -> impl Future<Output = Result<post::Model>>;
+    fn find_post(&self, id: post::Id) -> impl Future<Output = Result<Option<post::Model>, DbErr>>;
     fn get_root_post_of(&self, thread_id: thread::Id) -> impl Future<Output = Result<post::Model>>;
+    /// The first post of each thread in `thread_ids`.
+    fn get_root_posts_of(
+        &self,
+        thread_ids: &[thread::Id],
+    ) -> impl Future<Output = Result<HashMap<thread::Id, post::Model>, DbErr>>;
+    /// The newest approved reply in each thread in `thread_ids`, leaving out the threads'
+    /// root posts `root_ids`. Threads nobody has replied to are left out.
+    fn get_last_replies_of(
+        &self,
+        thread_ids: &[thread::Id],
+        root_ids: &[post::Id],
+    ) -> impl Future<Output = Result<HashMap<thread::Id, post::Model>, DbErr>>;
     fn insert_post(&self, post: post::NewModel) -> impl Future<Output = Result<post::Model>>;
+    fn approve_post(&self, id: post::Id) -> impl Future<Output = Result<post::Model>>;
+    fn publish_posts_due_by(
+        &self,
+        now: DateTime<Utc>,
+    ) -> impl Future<Output = Result<Vec<post::Model>, DbErr>>;
+    fn count_approved_posts_by(
+        &self,
+        author_id: user::Id,
+    ) -> impl Future<Output = Result<u64, DbErr>>;
+    /// Approved posts in each thread in `thread_ids`. Threads without any are left out.
+    fn count_approved_posts_in(
+        &self,
+        thread_ids: &[thread::Id],
+    ) -> impl Future<Output = Result<HashMap<thread::Id, u64>, DbErr>>;
+    /// Counts every post in the thread, including pending and scheduled ones.
+    fn count_posts_in(&self, thread_id: thread::Id) -> impl Future<Output = Result<u64, DbErr>>;
+    /// How many posts in the thread everyone can see, and the newest one's id.
+    fn summarize_public_posts_in(
+        &self,
+        thread_id: thread::Id,
+    ) -> impl Future<Output = Result<(u64, Option<post::Id>), DbErr>>;
+    /// Posts by `author_id` in the thread that are still pending or scheduled.
+    fn count_hidden_posts_by(
+        &self,
+        author_id: user::Id,
+        thread_id: thread::Id,
+    ) -> impl Future<Output = Result<u64, DbErr>>;
+
+    fn get_draft(
+        &self,
+        author_id: user::Id,
+        thread_id: thread::Id,
+    ) -> impl Future<Output = Result<Option<draft::Model>, DbErr>>;
+    fn save_draft(
+        &self,
+        author_id: user::Id,
+        thread_id: thread::Id,
+        body: String,
+    ) -> impl Future<Output = Result<(), DbErr>>;
+    fn delete_draft(
+        &self,
+        author_id: user::Id,
+        thread_id: thread::Id,
+    ) -> impl Future<Output = Result<(), DbErr>>;
+    fn purge_drafts_before(
+        &self,
+        cutoff: DateTime<Utc>,
+    ) -> impl Future<Output = Result<u64, DbErr>>;
 
     fn get_thread(&self, id: thread::Id) -> impl Future<Output = Result<thread::Model>>;
+    /// The tags of each thread in `thread_ids`, sorted. Untagged threads are left out.
+    fn get_thread_tags(
+        &self,
+        thread_ids: &[thread::Id],
+    ) -> impl Future<Output = Result<HashMap<thread::Id, Vec<String>>, DbErr>>;
+    fn get_threads_tagged(
+        &self,
+        tag: &str,
+    ) -> impl Future<Output = Result<Vec<thread::Model>, DbErr>>;
+    /// Counts the threads `viewer` can see.
+    fn count_threads(
+        &self,
+        viewer: Option<&user::Model>,
+    ) -> impl Future<Output = Result<u64, DbErr>>;
+    fn get_threads_page(
+        &self,
+        viewer: Option<&user::Model>,
+        offset: u64,
+        limit: u64,
+    ) -> impl Future<Output = Result<Vec<thread::Model>, DbErr>>;
+    fn get_newest_threads(
+        &self,
+        viewer: Option<&user::Model>,
+        limit: u64,
+    ) -> impl Future<Output = Result<Vec<thread::Model>, DbErr>>;
+    fn toggle_thread_pinned(&self, id: thread::Id) -> impl Future<Output = Result<thread::Model>>;
+    fn increment_thread_views(&self, id: thread::Id) -> impl Future<Output = Result<(), DbErr>>;
+    fn set_accepted_answer(
+        &self,
+        id: thread::Id,
+        post_id: Option<post::Id>,
+    ) -> impl Future<Output = Result<thread::Model>>;
+    fn get_posts_with_authors_of(
+        &self,
+        thread_id: thread::Id,
+    ) -> impl Future<Output = Result<(Vec<post::Model>, HashMap<user::Id, user::Model>), DbErr>>;
     fn get_thread_and_posts(
         &self,
         id: thread::Id,
@@ -64,6 +194,30 @@ impl DatabaseConnectionExt for DatabaseConnection {
         user::Entity::find_by_id(id).one(self).await
     }
 
+    async fn find_users(
+        &self,
+        ids: impl IntoIterator<Item = user::Id>,
+    ) -> Result<HashMap<user::Id, user::Model>, DbErr> {
+        let ids = ids.into_iter().collect::<HashSet<_>>();
+        if ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+        Ok(user::Entity::find()
+            .filter(user::Column::Id.is_in(ids))
+            .all(self)
+            .await?
+            .into_iter()
+            .map(|user| (user.id, user))
+            .collect())
+    }
+
+    async fn get_author(&self, id: user::Id) -> Result<user::Model, DbErr> {
+        Ok(self
+            .find_user(id)
+            .await?
+            .unwrap_or_else(|| user::Model::deleted(id)))
+    }
+
     async fn get_user_by_username(&self, username: impl Into<String>) -> Result<user::Model> {
         let username = username.into();
         Ok(user::Entity::find_by_username(username.clone())
@@ -76,6 +230,72 @@ impl DatabaseConnectionExt for DatabaseConnection {
         Ok(user.into_active_model().insert(self).await?)
     }
 
+    async fn set_signature(&self, id: user::Id, signature: Option<String>) -> Result<user::Model> {
+        let mut user = self.get_user(id).await?.into_active_model();
+        user.signature = Set(signature);
+        Ok(user.update(self).await?)
+    }
+
+    async fn set_timezone(&self, id: user::Id, timezone: Option<String>) -> Result<user::Model> {
+        let mut user = self.get_user(id).await?.into_active_model();
+        user.timezone = Set(timezone);
+        Ok(user.update(self).await?)
+    }
+
+    async fn delete_user(&self, id: user::Id, mode: user::DeleteMode) -> Result<()> {
+        let txn = self.begin().await?;
+        // Drafts are private and unsent, so neither mode keeps them.
+        draft::Entity::delete_many()
+            .filter(draft::Column::AuthorId.eq(id))
+            .exec(&txn)
+            .await?;
+        match mode {
+            user::DeleteMode::Reassign => {
+                let ghost = match user::Entity::find_by_username(user::DELETED_USERNAME)
+                    .one(&txn)
+                    .await?
+                {
+                    Some(ghost) => ghost,
+                    None => {
+                        user::NewModel {
+                            username: user::DELETED_USERNAME.into(),
+                            password: user::UNUSABLE_PASSWORD.into(),
+                        }
+                        .into_active_model()
+                        .insert(&txn)
+                        .await?
+                    }
+                };
+                post::Entity::update_many()
+                    .col_expr(post::Column::AuthorId, Expr::value(ghost.id))
+                    .filter(post::Column::AuthorId.eq(id))
+                    .exec(&txn)
+                    .await?;
+                user::Entity::delete_by_id(id).exec(&txn).await?;
+            }
+            user::DeleteMode::Anonymize => {
+                user::ActiveModel {
+                    id: Set(id),
+                    username: Set(format!("[deleted {id}]")),
+                    password: Set(user::UNUSABLE_PASSWORD.into()),
+                    avatar: Set(None),
+                    moderator: Set(false),
+                    signature: Set(None),
+                    created_at: NotSet,
+                    timezone: Set(None),
+                }
+                .update(&txn)
+                .await?;
+            }
+        }
+        txn.commit().await?;
+        if matches!(mode, user::DeleteMode::Reassign) {
+            // Deleting by id skips the model hooks, and the user's posts changed hands.
+            let _ = user::BROADCAST.send(BroadcastEvent::Delete);
+        }
+        Ok(())
+    }
+
     async fn find_user_by_username(
         &self,
         username: impl Into<String>,
@@ -83,6 +303,36 @@ impl DatabaseConnectionExt for DatabaseConnection {
         user::Entity::find_by_username(username).one(self).await
     }
 
+    async fn find_users_by_usernames(
+        &self,
+        usernames: impl IntoIterator<Item = String>,
+    ) -> Result<HashMap<String, user::Model>, DbErr> {
+        Ok(user::Entity::find()
+            .filter(user::Column::Username.is_in(usernames))
+            .all(self)
+            .await?
+            .into_iter()
+            .map(|user| (user.username.clone(), user))
+            .collect())
+    }
+
+    async fn search_users_by_prefix(
+        &self,
+        prefix: &str,
+        limit: u64,
+    ) -> Result<Vec<user::Model>, DbErr> {
+        let pattern = prefix
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        user::Entity::find()
+            .filter(user::Column::Username.starts_with(pattern))
+            .order_by_asc(user::Column::Username)
+            .limit(limit)
+            .all(self)
+            .await
+    }
+
     async fn get_post(&self, id: post::Id) -> Result<post::Model> {
         Ok(post::Entity::find_by_id(id)
             .one(self)
@@ -90,6 +340,10 @@ impl DatabaseConnectionExt for DatabaseConnection {
             .ok_or(anyhow!("Post {id} not found"))?)
     }
 
+    async fn find_post(&self, id: post::Id) -> Result<Option<post::Model>, DbErr> {
+        post::Entity::find_by_id(id).one(self).await
+    }
+
     async fn get_root_post_of(&self, thread_id: thread::Id) -> Result<post::Model> {
         Ok(post::Entity::find()
             .filter(post::Column::ThreadId.eq(thread_id))
@@ -99,12 +353,178 @@ impl DatabaseConnectionExt for DatabaseConnection {
             .ok_or(anyhow!("Thread {thread_id} has no root post"))?)
     }
 
+    async fn get_root_posts_of(
+        &self,
+        thread_ids: &[thread::Id],
+    ) -> Result<HashMap<thread::Id, post::Model>, DbErr> {
+        Ok(post::Entity::find()
+            .distinct_on([post::Column::ThreadId])
+            .filter(post::Column::ThreadId.is_in(thread_ids.iter().copied()))
+            .order_by_asc(post::Column::ThreadId)
+            .order_by_asc(post::Column::CreatedAt)
+            .all(self)
+            .await?
+            .into_iter()
+            .map(|post| (post.thread_id, post))
+            .collect())
+    }
+
+    async fn get_last_replies_of(
+        &self,
+        thread_ids: &[thread::Id],
+        root_ids: &[post::Id],
+    ) -> Result<HashMap<thread::Id, post::Model>, DbErr> {
+        Ok(post::Entity::find()
+            .distinct_on([post::Column::ThreadId])
+            .filter(post::Column::ThreadId.is_in(thread_ids.iter().copied()))
+            .filter(post::Column::Approved.eq(true))
+            .filter(post::Column::Published.eq(true))
+            .filter(post::Column::Id.is_not_in(root_ids.iter().copied()))
+            .order_by_asc(post::Column::ThreadId)
+            .order_by_desc(post::posted_at())
+            .all(self)
+            .await?
+            .into_iter()
+            .map(|post| (post.thread_id, post))
+            .collect())
+    }
+
     async fn insert_post(&self, post: post::NewModel) -> Result<post::Model> {
-        Ok(post
-            .into_active_model()
-            .into_active_model()
-            .insert(self)
-            .await?)
+        Ok(new_post(post).insert(self).await?)
+    }
+
+    async fn approve_post(&self, id: post::Id) -> Result<post::Model> {
+        let mut post = self.get_post(id).await?.into_active_model();
+        post.approved = Set(true);
+        Ok(post.update(self).await?)
+    }
+
+    /// Publishes every scheduled post due by `now` in one statement, so a post can't be
+    /// published twice. Bulk updates skip the model hooks, so this doesn't broadcast.
+    async fn publish_posts_due_by(&self, now: DateTime<Utc>) -> Result<Vec<post::Model>, DbErr> {
+        post::Entity::update_many()
+            .col_expr(post::Column::Published, Expr::value(true))
+            .filter(post::Column::Published.eq(false))
+            .filter(post::Column::PublishAt.lte(now))
+            .exec_with_returning(self)
+            .await
+    }
+
+    async fn count_approved_posts_by(&self, author_id: user::Id) -> Result<u64, DbErr> {
+        post::Entity::find()
+            .filter(post::Column::AuthorId.eq(author_id))
+            .filter(post::Column::Approved.eq(true))
+            .count(self)
+            .await
+    }
+
+    async fn count_approved_posts_in(
+        &self,
+        thread_ids: &[thread::Id],
+    ) -> Result<HashMap<thread::Id, u64>, DbErr> {
+        Ok(post::Entity::find()
+            .select_only()
+            .column(post::Column::ThreadId)
+            .column_as(post::Column::Id.count(), "count")
+            .filter(post::Column::ThreadId.is_in(thread_ids.iter().copied()))
+            .filter(post::Column::Approved.eq(true))
+            .filter(post::Column::Published.eq(true))
+            .group_by(post::Column::ThreadId)
+            .into_tuple::<(thread::Id, i64)>()
+            .all(self)
+            .await?
+            .into_iter()
+            .map(|(thread_id, count)| (thread_id, count as u64))
+            .collect())
+    }
+
+    async fn count_posts_in(&self, thread_id: thread::Id) -> Result<u64, DbErr> {
+        post::Entity::find()
+            .filter(post::Column::ThreadId.eq(thread_id))
+            .count(self)
+            .await
+    }
+
+    async fn summarize_public_posts_in(
+        &self,
+        thread_id: thread::Id,
+    ) -> Result<(u64, Option<post::Id>), DbErr> {
+        let (count, newest) = post::Entity::find()
+            .select_only()
+            .column_as(post::Column::Id.count(), "count")
+            .column_as(post::Column::Id.max(), "newest")
+            .filter(post::Column::ThreadId.eq(thread_id))
+            .filter(post::Column::Approved.eq(true))
+            .filter(post::Column::Published.eq(true))
+            .into_tuple::<(i64, Option<post::Id>)>()
+            .one(self)
+            .await?
+            .unwrap_or_default();
+        Ok((count as u64, newest))
+    }
+
+    async fn count_hidden_posts_by(
+        &self,
+        author_id: user::Id,
+        thread_id: thread::Id,
+    ) -> Result<u64, DbErr> {
+        post::Entity::find()
+            .filter(post::Column::ThreadId.eq(thread_id))
+            .filter(post::Column::AuthorId.eq(author_id))
+            .filter(
+                Condition::any()
+                    .add(post::Column::Approved.eq(false))
+                    .add(post::Column::Published.eq(false)),
+            )
+            .count(self)
+            .await
+    }
+
+    async fn get_draft(
+        &self,
+        author_id: user::Id,
+        thread_id: thread::Id,
+    ) -> Result<Option<draft::Model>, DbErr> {
+        draft::Entity::find_by_id((author_id, thread_id))
+            .one(self)
+            .await
+    }
+
+    async fn save_draft(
+        &self,
+        author_id: user::Id,
+        thread_id: thread::Id,
+        body: String,
+    ) -> Result<(), DbErr> {
+        draft::Entity::insert(draft::ActiveModel {
+            author_id: Set(author_id),
+            thread_id: Set(thread_id),
+            body: Set(body),
+            updated_at: Set(Utc::now()),
+        })
+        .on_conflict(
+            OnConflict::columns([draft::Column::AuthorId, draft::Column::ThreadId])
+                .update_columns([draft::Column::Body, draft::Column::UpdatedAt])
+                .to_owned(),
+        )
+        .exec(self)
+        .await?;
+        Ok(())
+    }
+
+    async fn delete_draft(&self, author_id: user::Id, thread_id: thread::Id) -> Result<(), DbErr> {
+        draft::Entity::delete_by_id((author_id, thread_id))
+            .exec(self)
+            .await?;
+        Ok(())
+    }
+
+    async fn purge_drafts_before(&self, cutoff: DateTime<Utc>) -> Result<u64, DbErr> {
+        Ok(draft::Entity::delete_many()
+            .filter(draft::Column::UpdatedAt.lt(cutoff))
+            .exec(self)
+            .await?
+            .rows_affected)
     }
 
     async fn get_thread(&self, id: thread::Id) -> Result<thread::Model> {
@@ -114,6 +534,102 @@ impl DatabaseConnectionExt for DatabaseConnection {
             .ok_or(anyhow!("Thread {id} not found"))?)
     }
 
+    async fn get_thread_tags(
+        &self,
+        thread_ids: &[thread::Id],
+    ) -> Result<HashMap<thread::Id, Vec<String>>, DbErr> {
+        Ok(thread_tag::Entity::find()
+            .filter(thread_tag::Column::ThreadId.is_in(thread_ids.iter().copied()))
+            .order_by_asc(thread_tag::Column::Tag)
+            .all(self)
+            .await?
+            .into_iter()
+            .into_group_map_by(|tag| tag.thread_id)
+            .into_iter()
+            .map(|(thread_id, tags)| (thread_id, tags.into_iter().map(|tag| tag.tag).collect()))
+            .collect())
+    }
+
+    async fn get_threads_tagged(&self, tag: &str) -> Result<Vec<thread::Model>, DbErr> {
+        thread::Entity::find()
+            .inner_join(thread_tag::Entity)
+            .filter(thread_tag::Column::Tag.eq(tag))
+            .order_by_desc(thread::Column::Pinned)
+            .order_by_asc(thread::Column::Id)
+            .all(self)
+            .await
+    }
+
+    async fn count_threads(&self, viewer: Option<&user::Model>) -> Result<u64, DbErr> {
+        thread::Entity::find()
+            .filter(visible_threads(viewer))
+            .count(self)
+            .await
+    }
+
+    /// The `limit` newest threads `viewer` can see, newest first.
+    async fn get_newest_threads(
+        &self,
+        viewer: Option<&user::Model>,
+        limit: u64,
+    ) -> Result<Vec<thread::Model>, DbErr> {
+        thread::Entity::find()
+            .filter(visible_threads(viewer))
+            .order_by_desc(thread::Column::Id)
+            .limit(limit)
+            .all(self)
+            .await
+    }
+
+    /// Threads `viewer` can see in forum order, `limit` at a time.
+    async fn get_threads_page(
+        &self,
+        viewer: Option<&user::Model>,
+        offset: u64,
+        limit: u64,
+    ) -> Result<Vec<thread::Model>, DbErr> {
+        thread::Entity::find()
+            .filter(visible_threads(viewer))
+            .order_by_desc(thread::Column::Pinned)
+            .order_by_asc(thread::Column::Id)
+            .offset(offset)
+            .limit(limit)
+            .all(self)
+            .await
+    }
+
+    async fn toggle_thread_pinned(&self, id: thread::Id) -> Result<thread::Model> {
+        let thread = self.get_thread(id).await?;
+        let pinned = !thread.pinned;
+        let mut thread = thread.into_active_model();
+        thread.pinned = Set(pinned);
+        Ok(thread.update(self).await?)
+    }
+
+    /// Adds one in the database rather than writing back a count read earlier, so
+    /// concurrent views all land. Doesn't broadcast, since nothing live shows views.
+    async fn increment_thread_views(&self, id: thread::Id) -> Result<(), DbErr> {
+        thread::Entity::update_many()
+            .col_expr(
+                thread::Column::Views,
+                Expr::col(thread::Column::Views).add(1),
+            )
+            .filter(thread::Column::Id.eq(id))
+            .exec(self)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_accepted_answer(
+        &self,
+        id: thread::Id,
+        post_id: Option<post::Id>,
+    ) -> Result<thread::Model> {
+        let mut thread = self.get_thread(id).await?.into_active_model();
+        thread.accepted_answer = Set(post_id);
+        Ok(thread.update(self).await?)
+    }
+
     async fn get_thread_and_posts(
         &self,
         id: thread::Id,
@@ -122,27 +638,24 @@ impl DatabaseConnectionExt for DatabaseConnection {
         Vec<post::Model>,
         HashMap<user::Id, user::Model>,
     )> {
-        let thread = thread::Entity::find_by_id(id)
-            .one(self)
-            .await?
-            .ok_or(anyhow!("Thread {id} not found"))?;
-        let posts = thread
-            .find_related(post::Entity)
-            .order_by_asc(post::Column::CreatedAt)
+        let thread = self.get_thread(id).await?;
+        let (posts, authors) = self.get_posts_with_authors_of(id).await?;
+        Ok((thread, posts, authors))
+    }
+
+    async fn get_posts_with_authors_of(
+        &self,
+        thread_id: thread::Id,
+    ) -> Result<(Vec<post::Model>, HashMap<user::Id, user::Model>), DbErr> {
+        let posts = post::Entity::find()
+            .filter(post::Column::ThreadId.eq(thread_id))
+            .order_by_asc(post::posted_at())
             .all(self)
             .await?;
-        let authors = posts
-            .iter()
-            .map(|post| post.author_id)
-            .collect::<HashSet<_>>();
-        let authors = user::Entity::find()
-            .filter(user::Column::Id.is_in(authors))
-            .all(self)
-            .await?
-            .into_iter()
-            .map(|author| (author.id, author))
-            .collect::<HashMap<_, _>>();
-        Ok((thread, posts, authors))
+        let authors = self
+            .find_users(posts.iter().map(|post| post.author_id))
+            .await?;
+        Ok((posts, authors))
     }
 
     async fn insert_thread(
@@ -151,23 +664,88 @@ impl DatabaseConnectionExt for DatabaseConnection {
     ) -> Result<(thread::Model, post::Model)> {
         let thread::NewModel {
             title,
+            slug,
+            tags,
             body,
             author_id,
+            approved,
+            publish_at,
         } = thread;
-        let thread = thread::ActiveModel {
+        // Inserted through the entities rather than the models, so the hooks don't
+        // broadcast anything before the thread and its root post are committed together.
+        let txn = self.begin().await?;
+        let thread = thread::Entity::insert(thread::ActiveModel {
             id: NotSet,
             title: Set(title),
-        }
-        .insert(self)
+            slug: Set(slug),
+            pinned: Set(false),
+            views: Set(0),
+            accepted_answer: Set(None),
+        })
+        .exec_with_returning(&txn)
         .await?;
-        let post = post::NewModel {
+        if !tags.is_empty() {
+            thread_tag::Entity::insert_many(tags.into_iter().map(|tag| thread_tag::ActiveModel {
+                tag: Set(tag),
+                thread_id: Set(thread.id),
+            }))
+            .exec(&txn)
+            .await?;
+        }
+        let post = post::Entity::insert(new_post(post::NewModel {
             body,
             author_id,
             thread_id: thread.id,
-        }
-        .into_active_model()
-        .insert(self)
+            approved,
+            publish_at,
+        }))
+        .exec_with_returning(&txn)
         .await?;
+        txn.commit().await?;
+        let _ = thread::BROADCAST.send(BroadcastEvent::Create(thread.clone()));
+        let _ = post::BROADCAST.send(BroadcastEvent::Create(post.clone()));
         Ok((thread, post))
     }
 }
+
+/// A new post, held back until its `publish_at` if it has one.
+fn new_post(post: post::NewModel) -> post::ActiveModel {
+    let scheduled = post.publish_at.is_some();
+    let mut post = post.into_active_model();
+    post.published = Set(!scheduled);
+    post
+}
+
+/// Threads whose opening post `viewer` can see, by the same rules as
+/// [`post::Model::visible_to`], so listings can be filtered and counted in the database.
+fn visible_threads(viewer: Option<&user::Model>) -> Condition {
+    let roots = Query::select()
+        .expr(post::Column::Id.min())
+        .from(post::Entity)
+        .group_by_col(post::Column::ThreadId)
+        .to_owned();
+    let mut visible = Condition::any().add(
+        Condition::all()
+            .add(post::Column::Approved.eq(true))
+            .add(post::Column::Published.eq(true)),
+    );
+    if let Some(viewer) = viewer {
+        visible = visible.add(post::Column::AuthorId.eq(viewer.id));
+        if viewer.moderator {
+            visible = visible.add(post::Column::Published.eq(true));
+        }
+    }
+    Condition::all().add(
+        thread::Column::Id.in_subquery(
+            Query::select()
+                .column(post::Column::ThreadId)
+                .from(post::Entity)
+                .cond_where(
+                    Condition::all()
+                        .add(post::Column::Id.in_subquery(roots))
+                        .add(visible),
+                )
+                .to_owned(),
+        ),
+    )
+}
@@ -1,7 +1,11 @@
+use std::time::Duration;
+
 use async_trait::async_trait;
+use chrono::Utc;
 use derive_more::Display;
 use lazy_static::lazy_static;
 use sea_orm::entity::prelude::*;
+use sea_orm::sea_query::{Func, SimpleExpr};
 use serde::{Deserialize, Serialize};
 use tokio::sync::broadcast::{Sender, channel};
 
@@ -31,6 +35,42 @@ pub struct Model {
     pub thread_id: thread::Id,
     #[sea_orm(belongs_to, relation_reverse = "Posts", from = "thread_id", to = "id")]
     pub thread: HasOne<thread::Entity>,
+    /// Unapproved posts are only shown to their author and moderators.
+    #[sea_orm(default_value = true)]
+    pub approved: bool,
+    /// When a scheduled post goes up. Kept once it's published, since that's when it's
+    /// shown as posted.
+    pub publish_at: Option<DateTimeUtc>,
+    /// False while a scheduled post waits for its `publish_at`. Until then only its author
+    /// sees it.
+    #[sea_orm(default_value = true)]
+    pub published: bool,
+}
+
+impl Model {
+    pub fn visible_to(&self, viewer: Option<&user::Model>) -> bool {
+        let is_author = viewer.is_some_and(|viewer| viewer.id == self.author_id);
+        if !self.published {
+            return is_author;
+        }
+        self.approved || is_author || viewer.is_some_and(|viewer| viewer.moderator)
+    }
+
+    /// Whether everyone can see the post.
+    pub fn is_public(&self) -> bool {
+        self.approved && self.published
+    }
+
+    /// When the post went up: when it was scheduled for, or else when it was written.
+    /// Threads are shown and ordered by this.
+    pub fn posted_at(&self) -> DateTimeUtc {
+        self.publish_at.unwrap_or(self.created_at)
+    }
+}
+
+/// [`Model::posted_at`] as a column expression, for ordering.
+pub fn posted_at() -> SimpleExpr {
+    Func::coalesce([Expr::col(Column::PublishAt), Expr::col(Column::CreatedAt)]).into()
 }
 
 #[derive(DeriveIntoActiveModel)]
@@ -39,6 +79,10 @@ pub struct NewModel {
     pub body: String,
     pub author_id: user::Id,
     pub thread_id: thread::Id,
+    pub approved: bool,
+    /// Holds the post back until then. It takes its place in the thread by when it goes up
+    /// rather than when it was written.
+    pub publish_at: Option<DateTimeUtc>,
 }
 
 #[async_trait]
@@ -65,6 +109,106 @@ impl ActiveModelBehavior for ActiveModel {
 }
 
 #[derive(
-    Clone, Copy, Debug, Display, Eq, PartialEq, Hash, DeriveValueType, Serialize, Deserialize,
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    Display,
+    Eq,
+    PartialEq,
+    Hash,
+    DeriveValueType,
+    Serialize,
+    Deserialize,
 )]
 pub struct Id(i64);
+
+/// Publishes scheduled posts as they come due, checking every [`PUBLISH_INTERVAL`].
+/// Publishing a thread's opening post also announces the thread on the forum stream.
+pub fn publish_scheduled(db: DatabaseConnection) {
+    const PUBLISH_INTERVAL: Duration = Duration::from_secs(30);
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PUBLISH_INTERVAL);
+        loop {
+            interval.tick().await;
+            let mut published = match db.publish_posts_due_by(Utc::now()).await {
+                Ok(published) => published,
+                Err(err) => {
+                    tracing::warn!("Failed to publish scheduled posts: {err}");
+                    continue;
+                }
+            };
+            published.sort_by_key(|post| (post.posted_at(), post.id));
+            for post in published {
+                tracing::debug!("Published scheduled post {}", post.id);
+                let _ = BROADCAST.send(BroadcastEvent::Update(post.clone()));
+                match db.get_root_post_of(post.thread_id).await {
+                    Ok(root) if root.id == post.id => match db.get_thread(post.thread_id).await {
+                        Ok(thread) => {
+                            let _ = thread::BROADCAST.send(BroadcastEvent::Update(thread));
+                        }
+                        Err(err) => tracing::warn!("Failed to announce thread: {err}"),
+                    },
+                    Ok(_) => {}
+                    Err(err) => tracing::warn!("Failed to announce thread: {err}"),
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(id: i64, moderator: bool) -> user::Model {
+        user::Model {
+            moderator,
+            ..user::Model::deleted(serde_json::from_value(id.into()).unwrap())
+        }
+    }
+
+    fn scheduled_by(author: &user::Model) -> Model {
+        Model {
+            id: Default::default(),
+            body: String::new(),
+            created_at: Default::default(),
+            author_id: author.id,
+            thread_id: Default::default(),
+            approved: true,
+            publish_at: Some(Utc::now() + chrono::Duration::hours(1)),
+            published: false,
+        }
+    }
+
+    #[test]
+    fn scheduled_posts_are_only_visible_to_their_author() {
+        let author = user(1, false);
+        let post = scheduled_by(&author);
+        assert!(post.visible_to(Some(&author)));
+        assert!(!post.visible_to(None));
+        assert!(!post.visible_to(Some(&user(2, false))));
+        assert!(!post.visible_to(Some(&user(3, true))));
+        assert!(!post.is_public());
+    }
+
+    #[test]
+    fn published_posts_are_visible_once_approved() {
+        let author = user(1, false);
+        let post = Model {
+            published: true,
+            ..scheduled_by(&author)
+        };
+        assert!(post.visible_to(None));
+        assert!(post.is_public());
+
+        let pending = Model {
+            approved: false,
+            ..post
+        };
+        assert!(!pending.visible_to(None));
+        assert!(pending.visible_to(Some(&author)));
+        assert!(pending.visible_to(Some(&user(3, true))));
+    }
+}
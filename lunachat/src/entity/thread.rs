@@ -2,7 +2,8 @@ use async_trait::async_trait;
 use derive_more::Display;
 use lazy_static::lazy_static;
 use sea_orm::entity::prelude::*;
-use serde::{Deserialize, Serialize};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize};
 use tokio::sync::broadcast::{Sender, channel};
 
 use crate::prelude::*;
@@ -18,6 +19,19 @@ pub struct Model {
     #[sea_orm(primary_key)]
     pub id: Id,
     pub title: String,
+    /// Cosmetic URL suffix derived from the title. Empty for threads created before slugs.
+    #[sea_orm(default_value = "")]
+    pub slug: String,
+    /// Pinned threads are listed before all others on the forum page.
+    #[sea_orm(default_value = false)]
+    pub pinned: bool,
+    /// The reply the thread's author marked as answering it.
+    pub accepted_answer: Option<post::Id>,
+    /// Page loads, counting each session at most once per [`ViewWindow`].
+    ///
+    /// [`ViewWindow`]: crate::views::ViewWindow
+    #[sea_orm(default_value = 0)]
+    pub views: i64,
     #[sea_orm(
         has_many,
         relation_enum = "Posts",
@@ -25,12 +39,57 @@ pub struct Model {
         on_update = "Cascade"
     )]
     pub posts: HasMany<super::post::Entity>,
+    #[sea_orm(
+        has_many,
+        relation_enum = "Tags",
+        on_delete = "Cascade",
+        on_update = "Cascade"
+    )]
+    pub tags: HasMany<super::thread_tag::Entity>,
+}
+
+impl Model {
+    /// Canonical path of the thread page, relative to the base path.
+    pub fn path(&self) -> String {
+        if self.slug.is_empty() {
+            format!("/thread/{}", self.id)
+        } else {
+            format!("/thread/{}-{}", self.id, self.slug)
+        }
+    }
+}
+
+/// Lowercases `title` and joins its alphanumeric runs with hyphens, truncated to a
+/// URL-friendly length.
+pub fn slugify(title: &str) -> String {
+    const MAX_LEN: usize = 60;
+
+    let mut slug = String::new();
+    for word in title
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+    {
+        let word = word.to_lowercase();
+        if !slug.is_empty() {
+            if slug.len() + 1 + word.len() > MAX_LEN {
+                break;
+            }
+            slug.push('-');
+        }
+        slug.push_str(&word);
+    }
+    slug.chars().take(MAX_LEN).collect()
 }
 
 pub struct NewModel {
     pub title: String,
+    pub slug: String,
+    pub tags: Vec<String>,
     pub body: String,
     pub author_id: user::Id,
+    pub approved: bool,
+    /// When the thread goes up, if it's scheduled. See [`post::NewModel::publish_at`].
+    pub publish_at: Option<DateTimeUtc>,
 }
 
 #[async_trait]
@@ -57,6 +116,98 @@ impl ActiveModelBehavior for ActiveModel {
 }
 
 #[derive(
-    Clone, Copy, Debug, Display, Eq, PartialEq, Hash, DeriveValueType, Serialize, Deserialize,
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    Display,
+    Eq,
+    PartialEq,
+    Hash,
+    DeriveValueType,
+    Serialize,
+    Deserialize,
 )]
 pub struct Id(i64);
+
+/// A `/thread/{key}` path segment: the numeric id, optionally followed by a cosmetic
+/// `-slug`. Only the id is used to look the thread up.
+#[derive(Clone, Debug)]
+pub struct Key {
+    pub id: Id,
+    pub slug: Option<String>,
+}
+
+impl<'de> Deserialize<'de> for Key {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let key = String::deserialize(deserializer)?;
+        let (id, slug) = match key.split_once('-') {
+            Some((id, slug)) => (id, Some(slug.to_string())),
+            None => (key.as_str(), None),
+        };
+        Ok(Key {
+            id: Id(id.parse().map_err(D::Error::custom)?),
+            slug,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::de::IntoDeserializer as _;
+    use serde::de::value::Error as ValueError;
+
+    use super::*;
+
+    fn key(key: &str) -> Result<Key, ValueError> {
+        Key::deserialize(key.into_deserializer())
+    }
+
+    #[test]
+    fn slugs_keep_unicode_words() {
+        assert_eq!(slugify("Café au lait? Ünïcode!"), "café-au-lait-ünïcode");
+        assert_eq!(slugify("ПРИВЕТ мир"), "привет-мир");
+    }
+
+    #[test]
+    fn titles_without_words_have_empty_slugs() {
+        assert_eq!(slugify("!!! ???"), "");
+        assert_eq!(slugify(""), "");
+    }
+
+    #[test]
+    fn long_slugs_stop_at_a_word_boundary() {
+        let slug = slugify(&"word ".repeat(30));
+        assert!(slug.len() <= 60);
+        assert!(slug.ends_with("word"));
+        assert_eq!(slugify(&"x".repeat(100)).chars().count(), 60);
+    }
+
+    #[test]
+    fn key_without_slug() {
+        let key = key("42").unwrap();
+        assert_eq!(key.id, Id(42));
+        assert_eq!(key.slug, None);
+    }
+
+    #[test]
+    fn key_slug_may_contain_hyphens() {
+        let key = key("42-hello-big-world").unwrap();
+        assert_eq!(key.id, Id(42));
+        assert_eq!(key.slug.as_deref(), Some("hello-big-world"));
+    }
+
+    #[test]
+    fn key_with_empty_slug() {
+        let key = key("42-").unwrap();
+        assert_eq!(key.id, Id(42));
+        assert_eq!(key.slug.as_deref(), Some(""));
+    }
+
+    #[test]
+    fn key_needs_a_numeric_id() {
+        assert!(key("hello-world").is_err());
+        assert!(key("-42").is_err());
+        assert!(key("").is_err());
+    }
+}
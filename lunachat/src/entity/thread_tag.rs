@@ -0,0 +1,51 @@
+use sea_orm::entity::prelude::*;
+
+use crate::prelude::*;
+
+/// Most tags a thread may have.
+pub const MAX_TAGS: usize = 5;
+/// Longest tag kept, in characters.
+pub const MAX_TAG_LEN: usize = 32;
+
+/// One tag on one thread. The primary key doubles as the index from a tag to its
+/// threads.
+#[sea_orm::model]
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "thread_tag")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub tag: String,
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub thread_id: thread::Id,
+    #[sea_orm(belongs_to, relation_reverse = "Tags", from = "thread_id", to = "id")]
+    pub thread: HasOne<thread::Entity>,
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+/// Normalizes a single tag to lowercase letters, digits and hyphens. Returns an empty
+/// string if nothing usable is left.
+pub fn normalize(tag: &str) -> String {
+    tag.trim()
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+        .chars()
+        .take(MAX_TAG_LEN)
+        .collect()
+}
+
+/// Parses comma-separated tags, normalizing and deduplicating them and keeping at most
+/// [`MAX_TAGS`].
+pub fn parse(tags: &str) -> Vec<String> {
+    let mut parsed = Vec::new();
+    for tag in tags.split(',').map(normalize) {
+        if !tag.is_empty() && !parsed.contains(&tag) {
+            parsed.push(tag);
+        }
+    }
+    parsed.truncate(MAX_TAGS);
+    parsed
+}
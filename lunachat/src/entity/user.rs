@@ -1,9 +1,18 @@
+use std::env;
+
+use async_trait::async_trait;
 use derive_more::Display;
+use lazy_static::lazy_static;
 use sea_orm::entity::prelude::*;
 use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{Sender, channel};
 
 use crate::prelude::*;
 
+lazy_static! {
+    pub static ref BROADCAST: Sender<BroadcastEvent<Model>> = channel(16).0;
+}
+
 #[sea_orm::model]
 #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
 #[sea_orm(table_name = "user")]
@@ -14,19 +23,109 @@ pub struct Model {
     pub username: String,
     pub password: String,
     pub avatar: Option<String>,
+    #[sea_orm(default_value = false)]
+    pub moderator: bool,
+    /// Sanitized HTML shown under each of the user's posts. Authors are looked up when
+    /// posts render, so changing it changes every old post too.
+    pub signature: Option<String>,
+    /// When the account was registered. `None` for accounts from before this was tracked.
+    pub created_at: Option<DateTimeUtc>,
+    /// IANA name of the timezone timestamps are shown in. `None` shows them in UTC.
+    pub timezone: Option<String>,
     #[sea_orm(has_many, relation_enum = "Posts", relation_reverse = "Author")]
     pub posts: HasMany<post::Entity>,
 }
 
+impl Model {
+    /// Stand-in for an author that no longer exists, so one missing row can't take down a
+    /// whole page.
+    pub fn deleted(id: Id) -> Self {
+        Self {
+            id,
+            username: DELETED_USERNAME.into(),
+            password: UNUSABLE_PASSWORD.into(),
+            avatar: None,
+            moderator: false,
+            signature: None,
+            created_at: None,
+            timezone: None,
+        }
+    }
+}
+
 #[derive(DeriveIntoActiveModel)]
+#[sea_orm(set(created_at = "Some(chrono::Utc::now())"))]
 pub struct NewModel {
     pub username: String,
     pub password: String,
 }
 
-impl ActiveModelBehavior for ActiveModel {}
+#[async_trait]
+impl ActiveModelBehavior for ActiveModel {
+    async fn after_save<C>(model: Model, _db: &C, insert: bool) -> Result<Model, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        if insert {
+            let _ = BROADCAST.send(BroadcastEvent::Create(model.clone()));
+        } else {
+            let _ = BROADCAST.send(BroadcastEvent::Update(model.clone()));
+        }
+        Ok(model)
+    }
+
+    async fn after_delete<C>(self, _db: &C) -> Result<Self, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        let _ = BROADCAST.send(BroadcastEvent::Delete);
+        Ok(self)
+    }
+}
+
+/// Username of the account that [`DeleteMode::Reassign`] hands posts over to.
+pub const DELETED_USERNAME: &str = "[deleted]";
+
+/// Password hash that no password verifies against.
+pub const UNUSABLE_PASSWORD: &str = "!";
+
+/// Whether `username` is one only deleted accounts may have.
+pub fn is_reserved(username: &str) -> bool {
+    username.starts_with("[deleted")
+}
+
+/// What happens to an account and its posts when it is deleted, from
+/// `LUNACHAT_DELETE_MODE`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DeleteMode {
+    /// Remove the account and move its posts to the shared [`DELETED_USERNAME`] account.
+    Reassign,
+    /// Keep the account row but blank its username and avatar and scramble its password.
+    #[default]
+    Anonymize,
+}
+
+impl DeleteMode {
+    pub fn from_env() -> Result<Self> {
+        match env::var("LUNACHAT_DELETE_MODE").as_deref() {
+            Err(_) | Ok("anonymize") => Ok(DeleteMode::Anonymize),
+            Ok("reassign") => Ok(DeleteMode::Reassign),
+            Ok(mode) => Err(anyhow!("Unknown LUNACHAT_DELETE_MODE {mode:?}")),
+        }
+    }
+}
 
 #[derive(
-    Copy, Clone, Debug, Display, Eq, PartialEq, Hash, DeriveValueType, Serialize, Deserialize,
+    Copy,
+    Clone,
+    Debug,
+    Default,
+    Display,
+    Eq,
+    PartialEq,
+    Hash,
+    DeriveValueType,
+    Serialize,
+    Deserialize,
 )]
 pub struct Id(i64);
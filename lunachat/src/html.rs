@@ -0,0 +1,132 @@
+/// A piece of sanitized HTML, as split up by [`segments`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Segment<'a> {
+    /// A tag, attributes and all.
+    Tag(&'a str),
+    /// Text outside any of the skipped elements.
+    Text(&'a str),
+    /// Text inside one of the skipped elements.
+    Skipped(&'a str),
+}
+
+/// Splits sanitized `html` into tags and the text between them, marking text inside any
+/// of the `skip` elements (such as `a`, `code` or `pre`) so it can be left alone.
+///
+/// This relies on the sanitizer's output being well-formed: comments are gone and `<`
+/// only appears in text as `&lt;`.
+pub fn segments<'a>(html: &'a str, skip: &[&str]) -> Vec<Segment<'a>> {
+    let mut segments = Vec::new();
+    let mut skip_depth = 0usize;
+    let mut rest = html;
+    while !rest.is_empty() {
+        if rest.starts_with('<') {
+            let end = tag_end(rest);
+            let tag = &rest[..end];
+            if let Some(name) = tag_name(tag)
+                && skip.contains(&name.as_str())
+            {
+                if tag.starts_with("</") {
+                    skip_depth = skip_depth.saturating_sub(1);
+                } else if !tag.ends_with("/>") {
+                    skip_depth += 1;
+                }
+            }
+            segments.push(Segment::Tag(tag));
+            rest = &rest[end..];
+        } else {
+            let end = rest.find('<').unwrap_or(rest.len());
+            let text = &rest[..end];
+            segments.push(if skip_depth > 0 {
+                Segment::Skipped(text)
+            } else {
+                Segment::Text(text)
+            });
+            rest = &rest[end..];
+        }
+    }
+    segments
+}
+
+/// Rebuilds `html` with each text node outside the `skip` elements passed through
+/// `rewrite`, which appends its replacement to the output. Tags, attribute values and
+/// skipped text are copied as they are.
+pub fn rewrite_text(
+    html: &str,
+    skip: &[&str],
+    mut rewrite: impl FnMut(&str, &mut String),
+) -> String {
+    let mut out = String::with_capacity(html.len());
+    for segment in segments(html, skip) {
+        match segment {
+            Segment::Tag(markup) | Segment::Skipped(markup) => out.push_str(markup),
+            Segment::Text(text) => rewrite(text, &mut out),
+        }
+    }
+    out
+}
+
+/// Length of the tag at the start of `html`, up to and including its `>`. Quoted
+/// attribute values may contain `>`.
+fn tag_end(html: &str) -> usize {
+    let mut quote = None;
+    for (index, char) in html.char_indices() {
+        match (quote, char) {
+            (None, '"' | '\'') => quote = Some(char),
+            (Some(open), _) if char == open => quote = None,
+            (None, '>') => return index + 1,
+            _ => {}
+        }
+    }
+    html.len()
+}
+
+fn tag_name(tag: &str) -> Option<String> {
+    let name = tag.trim_start_matches('<').trim_start_matches('/');
+    let end = name
+        .find(|char: char| !char.is_ascii_alphanumeric())
+        .unwrap_or(name.len());
+    Some(name[..end].to_ascii_lowercase()).filter(|name| !name.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_inside_skipped_elements_is_marked() {
+        assert_eq!(
+            segments("a<code>b</code>c", &["code"]),
+            [
+                Segment::Text("a"),
+                Segment::Tag("<code>"),
+                Segment::Skipped("b"),
+                Segment::Tag("</code>"),
+                Segment::Text("c"),
+            ]
+        );
+    }
+
+    #[test]
+    fn quoted_attributes_may_contain_angle_brackets() {
+        assert_eq!(
+            segments(r#"<a title="x>y">z</a>"#, &[]),
+            [
+                Segment::Tag(r#"<a title="x>y">"#),
+                Segment::Text("z"),
+                Segment::Tag("</a>"),
+            ]
+        );
+    }
+
+    #[test]
+    fn nested_skipped_elements_stay_skipped() {
+        assert_eq!(
+            rewrite_text(
+                "<pre><code>x</code>y</pre>z",
+                &["code", "pre"],
+                |text, out| out.push_str(&text.to_uppercase())
+            ),
+            "<pre><code>x</code>y</pre>Z"
+        );
+    }
+}
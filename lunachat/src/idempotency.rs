@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::prelude::*;
+
+/// How long a client may retry a submission and get the original result back.
+const TTL: Duration = Duration::from_secs(10 * 60);
+
+/// A fresh key for a compose form, so each submission of it can be told from a retry.
+pub fn new_key() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
+enum Slot<T> {
+    /// The first submission with this key is still being handled.
+    Pending,
+    Done(T),
+}
+
+type Scope = (user::Id, String);
+
+/// Remembers what each `(author, idempotency key)` pair created, so a repeated submission
+/// returns the existing post or thread instead of creating a duplicate.
+///
+/// A key is [claimed](Self::claim) before anything is written, so two copies of the same
+/// submission arriving at once can't both get through.
+pub struct IdempotencyKeys<T> {
+    slots: Arc<Mutex<HashMap<Scope, (Slot<T>, Instant)>>>,
+}
+
+impl<T> Clone for IdempotencyKeys<T> {
+    fn clone(&self) -> Self {
+        Self {
+            slots: self.slots.clone(),
+        }
+    }
+}
+
+impl<T> Default for IdempotencyKeys<T> {
+    fn default() -> Self {
+        Self {
+            slots: Default::default(),
+        }
+    }
+}
+
+pub enum Claim<T: Copy> {
+    /// Nobody has used the key yet. Complete the reservation once the submission is saved.
+    New(Reservation<T>),
+    /// A previous submission with this key created `T`.
+    Done(T),
+    /// Another submission with this key is being handled right now.
+    InFlight,
+}
+
+impl<T: Copy> IdempotencyKeys<T> {
+    pub fn claim(&self, author_id: user::Id, key: String) -> Claim<T> {
+        let mut slots = self.slots.lock().unwrap_or_else(PoisonError::into_inner);
+        slots.retain(|_, (_, claimed)| claimed.elapsed() < TTL);
+
+        let scope = (author_id, key);
+        match slots.get(&scope) {
+            Some((Slot::Done(created), _)) => Claim::Done(*created),
+            Some((Slot::Pending, _)) => Claim::InFlight,
+            None => {
+                slots.insert(scope.clone(), (Slot::Pending, Instant::now()));
+                Claim::New(Reservation {
+                    keys: self.clone(),
+                    scope: Some(scope),
+                })
+            }
+        }
+    }
+}
+
+/// A claimed key. Dropping it without [completing](Self::complete) it, because the
+/// submission failed validation or errored, frees the key for a retry.
+pub struct Reservation<T: Copy> {
+    keys: IdempotencyKeys<T>,
+    scope: Option<Scope>,
+}
+
+impl<T: Copy> Reservation<T> {
+    pub fn complete(mut self, created: T) {
+        let Some(scope) = self.scope.take() else {
+            return;
+        };
+        let mut slots = self
+            .keys
+            .slots
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        slots.insert(scope, (Slot::Done(created), Instant::now()));
+    }
+}
+
+impl<T: Copy> Drop for Reservation<T> {
+    fn drop(&mut self) {
+        let Some(scope) = self.scope.take() else {
+            return;
+        };
+        let mut slots = self
+            .keys
+            .slots
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some((Slot::Pending, _)) = slots.get(&scope) {
+            slots.remove(&scope);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn author() -> user::Id {
+        Default::default()
+    }
+
+    #[test]
+    fn same_key_twice_creates_once() {
+        let keys = IdempotencyKeys::<u32>::default();
+        let Claim::New(reservation) = keys.claim(author(), "key".into()) else {
+            panic!("first claim should be new");
+        };
+        reservation.complete(7);
+        assert!(matches!(keys.claim(author(), "key".into()), Claim::Done(7)));
+    }
+
+    #[test]
+    fn concurrent_duplicate_is_held_off() {
+        let keys = IdempotencyKeys::<u32>::default();
+        let _first = keys.claim(author(), "key".into());
+        assert!(matches!(
+            keys.claim(author(), "key".into()),
+            Claim::InFlight
+        ));
+    }
+
+    #[test]
+    fn failed_submission_frees_the_key() {
+        let keys = IdempotencyKeys::<u32>::default();
+        drop(keys.claim(author(), "key".into()));
+        assert!(matches!(keys.claim(author(), "key".into()), Claim::New(_)));
+    }
+
+    #[test]
+    fn different_keys_are_independent() {
+        let keys = IdempotencyKeys::<u32>::default();
+        let _first = keys.claim(author(), "one".into());
+        assert!(matches!(keys.claim(author(), "two".into()), Claim::New(_)));
+    }
+}
@@ -1,47 +1,253 @@
 use std::env;
+use std::time::Duration;
 
-use axum::{Extension, Router};
+use axum::extract::DefaultBodyLimit;
+use axum::http::Request;
+use axum::{Extension, Router, middleware};
 use axum_login::AuthManagerLayerBuilder;
-use axum_login::tower_sessions::{MemoryStore, SessionManagerLayer};
+use axum_login::tower_sessions::MemoryStore;
 use sea_orm::Database;
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
+use tower_http::trace::TraceLayer;
 
-use crate::auth::Backend;
+use crate::auth::{Backend, LoginLockout, ThreadCreators};
+use crate::csrf::OriginCheck;
+use crate::idempotency::IdempotencyKeys;
+use crate::maintenance::Maintenance;
+use crate::metrics::{METRICS, MetricsToken};
+use crate::moderation::{ApprovalThreshold, MaxPostsPerThread, MinAccountAge};
+use crate::pagination::PageSize;
+use crate::pipeline::BodyPipeline;
 use crate::prelude::*;
-use crate::sanitizer::Sanitizer;
+use crate::proxy::TrustedProxies;
+use crate::render_cache::RenderCache;
+use crate::robots::RobotsPolicy;
+use crate::sanitizer::{ImagePolicy, Sanitizer, SanitizerConfig, TrackingParams};
+use crate::security::SecurityHeaders;
+use crate::session::IdleTimeout;
+use crate::sse::{SseConfig, SseLimiter};
+use crate::unicode::TextNormalizer;
+use crate::usernames::ReservedUsernames;
+use crate::views::ViewWindow;
+use crate::webhooks::Webhooks;
+use crate::word_filter::WordFilter;
 
+pub mod api;
 pub mod auth;
+pub mod cors;
+pub mod csrf;
+pub mod emoji;
 pub mod entity;
+pub mod html;
+pub mod idempotency;
+pub mod logging;
+pub mod maintenance;
+pub mod mentions;
+pub mod metrics;
+pub mod moderation;
+pub mod negotiate;
+pub mod pagination;
+pub mod paths;
+pub mod pipeline;
 pub mod prelude;
+pub mod proxy;
+pub mod render_cache;
+pub mod robots;
 pub mod sanitizer;
+pub mod security;
+pub mod session;
+pub mod sse;
+pub mod submission;
 pub mod templates;
+pub mod timezone;
+pub mod unicode;
+pub mod usernames;
+pub mod validation;
+pub mod views;
+pub mod webhooks;
+pub mod word_filter;
+
+pub use paths::{absolute_url, base_path, url};
+
+/// Largest request body accepted by any route. Everything we take is a small form, so
+/// anything bigger is refused with 413 before it's buffered.
+pub const FORM_BODY_LIMIT: usize = 256 * 1024;
+
+/// Longest a non-streaming request may take before it's answered with 504, from
+/// `LUNACHAT_REQUEST_TIMEOUT` in seconds. Defaults to 30 seconds.
+pub fn request_timeout() -> Result<Duration> {
+    match env::var("LUNACHAT_REQUEST_TIMEOUT") {
+        Ok(secs) => Ok(Duration::from_secs(secs.parse()?)),
+        Err(_) => Ok(Duration::from_secs(30)),
+    }
+}
 
 pub async fn apply_middleware(router: Router) -> Result<Router> {
     // DB
     let database_url = env::var("DATABASE_URL")?;
     tracing::debug!("Connecting to database at {database_url}");
-    let db: DatabaseConnection = Database::connect(database_url).await?;
+    let mut db: DatabaseConnection = Database::connect(database_url).await?;
     tracing::debug!("Connected to database");
+    let slow_query = logging::slow_query_threshold()?;
+    db.set_metric_callback(move |info| {
+        METRICS.record_db_query(info.elapsed);
+        if let Some(threshold) = slow_query
+            && info.elapsed > threshold
+        {
+            // Only the SQL, since the bound values can be post bodies or passwords.
+            tracing::warn!(
+                elapsed_ms = info.elapsed.as_millis() as u64,
+                failed = info.failed,
+                sql = %info.statement.sql,
+                "Slow query"
+            );
+        }
+    });
     db.get_schema_registry("lunachat::entity::*")
         .sync(&db)
         .await?;
 
+    // Drafts
+    let draft_ttl = match env::var("LUNACHAT_DRAFT_TTL_DAYS") {
+        Ok(days) => Duration::from_secs(days.parse::<u64>()? * 24 * 60 * 60),
+        Err(_) => Duration::from_secs(30 * 24 * 60 * 60),
+    };
+    draft::purge_expired(db.clone(), draft_ttl);
+
+    // Scheduled posts
+    post::publish_scheduled(db.clone());
+
     // Session layer
     let session_store = MemoryStore::default();
-    let session_layer = SessionManagerLayer::new(session_store);
+    let session_layer = session::layer(session_store)?;
+    let idle_timeout = IdleTimeout::from_env()?;
 
     // Auth service
-    let backend = Backend::new(db.clone());
+    let backend = Backend::new(
+        db.clone(),
+        LoginLockout::from_env()?,
+        ThreadCreators::from_env()?,
+    );
     let auth_layer = AuthManagerLayerBuilder::new(backend, session_layer).build();
 
     // Sanitizer
-    let mut builder = ammonia::Builder::new();
-    builder.add_generic_attributes(["style"]);
-    let sanitizer = Sanitizer::new(builder);
+    let sanitizer_config = SanitizerConfig::from_env().leak();
+    let sanitizer = Sanitizer::new(
+        |builder| sanitizer_config.configure(builder),
+        ImagePolicy::from_env(),
+        TrackingParams::from_env(),
+    )?;
+
+    // Metrics
+    let metrics_token = MetricsToken(env::var("LUNACHAT_METRICS_TOKEN").ok());
+
+    // Approval queue
+    let approval_threshold = ApprovalThreshold(match env::var("LUNACHAT_APPROVAL_THRESHOLD") {
+        Ok(threshold) => Some(threshold.parse()?).filter(|&threshold| threshold > 0),
+        Err(_) => None,
+    });
+
+    // Unicode normalization
+    let text_normalizer = TextNormalizer::from_env()?;
+
+    // Post bodies
+    let body_pipeline = BodyPipeline::from_env(sanitizer.clone(), text_normalizer)?;
+
+    // Word filter
+    let word_filter = WordFilter::from_env()?;
+    word_filter.reload_on_sighup()?;
+
+    // Live updates
+    let sse_config = SseConfig::from_env()?;
+    let sse_limiter = SseLimiter::from_env()?;
+    let trusted_proxies = TrustedProxies::from_env()?;
+
+    // Pagination
+    let page_size = PageSize::from_env()?;
+
+    // Thread page cache
+    let render_cache = RenderCache::from_env()?;
+
+    // View counts
+    let view_window = ViewWindow::from_env()?;
+
+    // Crawlers
+    let robots = RobotsPolicy::from_env()?;
+
+    // Account deletion
+    let delete_mode = user::DeleteMode::from_env()?;
+
+    // Maintenance mode
+    let maintenance = Maintenance::from_env();
+
+    // Security headers
+    let security_headers = SecurityHeaders::from_env()?;
+
+    // Webhooks
+    let webhooks = Webhooks::from_env()?;
+
+    // Cross-site request checks
+    let origin_check = OriginCheck::from_env()?;
 
     let router = router
+        .layer(DefaultBodyLimit::max(FORM_BODY_LIMIT))
+        .layer(middleware::from_fn_with_state(
+            idle_timeout,
+            session::expire_idle,
+        ))
         .layer(auth_layer)
+        .layer(Extension(render_cache))
+        .layer(Extension(page_size))
+        .layer(Extension(robots))
+        .layer(Extension(view_window))
+        .layer(Extension(sse_config))
+        .layer(Extension(sse_limiter))
+        .layer(Extension(trusted_proxies))
+        .layer(Extension(IdempotencyKeys::<post::Id>::default()))
+        .layer(Extension(IdempotencyKeys::<thread::Id>::default()))
+        .layer(Extension(delete_mode))
+        .layer(Extension(webhooks))
+        .layer(Extension(ReservedUsernames::from_env()))
+        .layer(Extension(word_filter))
+        .layer(Extension(text_normalizer))
+        .layer(Extension(body_pipeline))
+        .layer(Extension(approval_threshold))
+        .layer(Extension(MinAccountAge::from_env()?))
+        .layer(Extension(MaxPostsPerThread::from_env()?))
+        .layer(Extension(metrics_token))
         .layer(Extension(sanitizer))
-        .layer(Extension(db));
+        .layer(Extension(db))
+        .layer(Extension(maintenance.clone()))
+        .layer(middleware::from_fn_with_state(
+            maintenance,
+            maintenance::reject_writes,
+        ))
+        .layer(middleware::from_fn_with_state(
+            origin_check,
+            csrf::check_origin,
+        ))
+        .layer(middleware::from_fn(api::json_errors))
+        .layer(middleware::from_fn_with_state(
+            security_headers,
+            security::apply,
+        ))
+        .layer(
+            TraceLayer::new_for_http().make_span_with(|req: &Request<_>| {
+                let request_id = req
+                    .headers()
+                    .get("x-request-id")
+                    .and_then(|id| id.to_str().ok())
+                    .unwrap_or_default();
+                tracing::info_span!(
+                    "request",
+                    method = %req.method(),
+                    uri = %req.uri(),
+                    request_id,
+                )
+            }),
+        )
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid));
 
     Ok(router)
 }
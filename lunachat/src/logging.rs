@@ -0,0 +1,47 @@
+use std::env;
+use std::io::IsTerminal as _;
+use std::time::Duration;
+
+use tracing_subscriber::EnvFilter;
+
+use crate::prelude::*;
+
+const DEFAULT_FILTER: &str = "debug,lunachat=trace,main=trace,sqlx=warn";
+
+/// Installs the global subscriber. `LUNACHAT_LOG_FORMAT` picks `pretty`, `compact` or
+/// `json`, defaulting to `pretty` on a terminal and `json` otherwise. `RUST_LOG`
+/// overrides the default filter.
+pub fn init() -> Result<()> {
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_FILTER));
+    let format = match env::var("LUNACHAT_LOG_FORMAT") {
+        Ok(format) => format,
+        Err(_) if std::io::stdout().is_terminal() => "pretty".into(),
+        Err(_) => "json".into(),
+    };
+
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    match format.as_str() {
+        "pretty" => builder.pretty().init(),
+        "compact" => builder.compact().init(),
+        // Only the innermost span is flattened into each line, which is where the
+        // request id lives.
+        "json" => builder
+            .json()
+            .with_current_span(true)
+            .with_span_list(false)
+            .init(),
+        format => return Err(anyhow!("Unknown LUNACHAT_LOG_FORMAT {format:?}")),
+    }
+    Ok(())
+}
+
+/// Queries slower than this are logged at `warn`, from `LUNACHAT_SLOW_QUERY_MS`. Defaults
+/// to 100ms; `0` turns the log off.
+pub fn slow_query_threshold() -> Result<Option<Duration>> {
+    let millis = match env::var("LUNACHAT_SLOW_QUERY_MS") {
+        Ok(millis) => millis.parse()?,
+        Err(_) => 100,
+    };
+    Ok(Some(Duration::from_millis(millis)).filter(|threshold| !threshold.is_zero()))
+}
@@ -0,0 +1,80 @@
+use std::env;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use axum::extract::{Request, State};
+use axum::http::header::RETRY_AFTER;
+use axum::http::{Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse as _, Response};
+
+use crate::prelude::*;
+
+/// How long clients are told to wait before retrying a refused write, in seconds.
+const RETRY_AFTER_SECS: u32 = 120;
+
+/// Whether writes are frozen, shared by every clone.
+#[derive(Clone, Debug, Default)]
+pub struct Maintenance(Arc<AtomicBool>);
+
+impl Maintenance {
+    /// Starts in maintenance mode if `LUNACHAT_MAINTENANCE` is `1` or `true`.
+    pub fn from_env() -> Self {
+        let active = env::var("LUNACHAT_MAINTENANCE")
+            .is_ok_and(|value| matches!(value.trim(), "1" | "true"));
+        Self(Arc::new(AtomicBool::new(active)))
+    }
+
+    /// Whether writes are currently frozen. Pages use this to show a banner.
+    pub fn is_active(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Flips maintenance mode and returns the new state.
+    pub fn toggle(&self) -> bool {
+        !self.0.fetch_xor(true, Ordering::Relaxed)
+    }
+}
+
+/// Answers state-changing requests with 503 while maintenance mode is on. Reads and
+/// streams keep working. Logging in and out only touch the session, and toggling
+/// maintenance itself has to get through, so those are let past.
+pub async fn reject_writes(
+    State(maintenance): State<Maintenance>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let exempt = [
+        crate::url("/admin/maintenance"),
+        crate::url("/login"),
+        crate::url("/logout"),
+    ];
+    if !maintenance.is_active()
+        || matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS)
+        || exempt.iter().any(|path| req.uri().path() == path)
+    {
+        return next.run(req).await;
+    }
+
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        [(RETRY_AFTER, RETRY_AFTER_SECS.to_string())],
+        "Lunachat is in maintenance mode, try again shortly",
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toggle_is_shared_between_clones() {
+        let maintenance = Maintenance::default();
+        let handle = maintenance.clone();
+        assert!(handle.toggle());
+        assert!(maintenance.is_active());
+        assert!(!maintenance.toggle());
+        assert!(!handle.is_active());
+    }
+}
@@ -0,0 +1,96 @@
+use std::collections::HashSet;
+
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+
+use crate::html::{self, Segment};
+use crate::prelude::*;
+
+lazy_static! {
+    static ref MENTION: Regex = Regex::new(r"(^|[\s(])@([A-Za-z0-9_\-]{1,32})").unwrap();
+}
+
+/// Elements whose text is never linked: links can't nest, and code is shown as written.
+const SKIP: &[&str] = &["a", "code", "pre"];
+
+/// Turns `@username` tokens in the text of an already-sanitized body into profile links.
+/// Mentions of users that don't exist, and anything in attributes, links or code, are
+/// left as they are.
+pub async fn link_mentions(db: &DatabaseConnection, body: String) -> Result<String> {
+    let usernames = mentioned(&body);
+    if usernames.is_empty() {
+        return Ok(body);
+    }
+
+    let users = db.find_users_by_usernames(usernames).await?;
+    Ok(link(&body, |username| {
+        users
+            .get(username)
+            .map(|user| (user.id, user.username.clone()))
+    }))
+}
+
+fn mentioned(html: &str) -> HashSet<String> {
+    html::segments(html, SKIP)
+        .into_iter()
+        .filter_map(|segment| match segment {
+            Segment::Text(text) => Some(text),
+            _ => None,
+        })
+        .flat_map(|text| MENTION.captures_iter(text))
+        .map(|caps| caps[2].to_string())
+        .collect()
+}
+
+fn link(html: &str, lookup: impl Fn(&str) -> Option<(user::Id, String)>) -> String {
+    html::rewrite_text(html, SKIP, |text, out| {
+        let linked = MENTION.replace_all(text, |caps: &Captures| match lookup(&caps[2]) {
+            Some((id, username)) => format!(
+                r#"{}<a href="{}" class="username mention">@{}</a>"#,
+                &caps[1],
+                crate::url(&format!("/user/{id}")),
+                username
+            ),
+            None => caps[0].to_string(),
+        });
+        out.push_str(&linked);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn link_bob(html: &str) -> String {
+        link(html, |username| {
+            (username == "bob").then(|| (Default::default(), "bob".into()))
+        })
+    }
+
+    #[test]
+    fn mentions_in_text_are_linked() {
+        let linked = link_bob("<p>hi @bob and @nobody</p>");
+        assert!(linked.contains(r#"class="username mention">@bob</a>"#));
+        assert!(linked.contains("and @nobody</p>"));
+    }
+
+    #[test]
+    fn attributes_are_left_alone() {
+        let html = r#"<span title=" @bob">x</span>"#;
+        assert_eq!(link_bob(html), html);
+        assert!(mentioned(html).is_empty());
+    }
+
+    #[test]
+    fn mentions_inside_links_are_not_nested() {
+        let html = r#"<a href="https://example.com"> @bob</a>"#;
+        assert_eq!(link_bob(html), html);
+    }
+
+    #[test]
+    fn mentions_inside_code_are_left_alone() {
+        let html = "<pre><code> @bob</code></pre><code>@bob</code>";
+        assert_eq!(link_bob(html), html);
+        assert!(mentioned(html).is_empty());
+    }
+}
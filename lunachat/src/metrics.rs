@@ -0,0 +1,217 @@
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::time::Duration;
+
+use axum::http::HeaderMap;
+use axum::http::header::AUTHORIZATION;
+use hmac::{Hmac, Mac as _};
+use sha2::Sha256;
+
+pub static METRICS: Metrics = Metrics::new();
+
+pub struct Metrics {
+    pub posts_created: Counter,
+    pub threads_created: Counter,
+    pub logins: Counter,
+    pub login_failures: Counter,
+    pub sse_connections: Gauge,
+    db_queries: Counter,
+    db_query_micros: Counter,
+}
+
+impl Metrics {
+    const fn new() -> Self {
+        Self {
+            posts_created: Counter::new(),
+            threads_created: Counter::new(),
+            logins: Counter::new(),
+            login_failures: Counter::new(),
+            sse_connections: Gauge::new(),
+            db_queries: Counter::new(),
+            db_query_micros: Counter::new(),
+        }
+    }
+
+    pub fn record_db_query(&self, elapsed: Duration) {
+        self.db_queries.inc();
+        self.db_query_micros.add(elapsed.as_micros() as u64);
+    }
+
+    /// Renders every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for (name, help, counter) in [
+            (
+                "lunachat_posts_created_total",
+                "Replies created.",
+                &self.posts_created,
+            ),
+            (
+                "lunachat_threads_created_total",
+                "Threads created.",
+                &self.threads_created,
+            ),
+            ("lunachat_logins_total", "Successful logins.", &self.logins),
+            (
+                "lunachat_login_failures_total",
+                "Failed login attempts.",
+                &self.login_failures,
+            ),
+        ] {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} counter");
+            let _ = writeln!(out, "{name} {}", counter.get());
+        }
+
+        let _ = writeln!(out, "# HELP lunachat_sse_connections Open SSE streams.");
+        let _ = writeln!(out, "# TYPE lunachat_sse_connections gauge");
+        let _ = writeln!(
+            out,
+            "lunachat_sse_connections {}",
+            self.sse_connections.get()
+        );
+
+        let _ = writeln!(
+            out,
+            "# HELP lunachat_db_query_duration_seconds Time spent in database queries."
+        );
+        let _ = writeln!(out, "# TYPE lunachat_db_query_duration_seconds summary");
+        let _ = writeln!(
+            out,
+            "lunachat_db_query_duration_seconds_sum {}",
+            self.db_query_micros.get() as f64 / 1_000_000.0
+        );
+        let _ = writeln!(
+            out,
+            "lunachat_db_query_duration_seconds_count {}",
+            self.db_queries.get()
+        );
+
+        out
+    }
+}
+
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    const fn new() -> Self {
+        Self(AtomicU64::new(0))
+    }
+
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+pub struct Gauge(AtomicI64);
+
+impl Gauge {
+    const fn new() -> Self {
+        Self(AtomicI64::new(0))
+    }
+
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dec(&self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Counts an open SSE stream for as long as it is alive. The stream is dropped as soon
+/// as the client goes away, even mid-await, so this is where disconnects are noticed.
+pub struct SseConnection {
+    /// What the stream is following, for logs.
+    topic: String,
+}
+
+impl SseConnection {
+    pub fn open(topic: impl Into<String>) -> Self {
+        let topic = topic.into();
+        tracing::debug!("SSE connected for {topic}");
+        METRICS.sse_connections.inc();
+        SseConnection { topic }
+    }
+}
+
+impl Drop for SseConnection {
+    fn drop(&mut self) {
+        METRICS.sse_connections.dec();
+        tracing::debug!("SSE disconnected for {}", self.topic);
+    }
+}
+
+/// Bearer token required to scrape `/metrics`. The endpoint is disabled when unset.
+#[derive(Clone)]
+pub struct MetricsToken(pub Option<String>);
+
+impl MetricsToken {
+    pub fn authorizes(&self, headers: &HeaderMap) -> bool {
+        let Some(expected) = &self.0 else {
+            return false;
+        };
+        headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|token| tokens_match(expected, token))
+    }
+}
+
+/// Compares MACs of the two tokens in constant time, so response timing doesn't give
+/// away how much of a guess was right.
+fn tokens_match(expected: &str, token: &str) -> bool {
+    let mac = |value: &str| {
+        let mut mac = Hmac::<Sha256>::new_from_slice(expected.as_bytes())
+            .expect("HMAC takes keys of any size");
+        mac.update(value.as_bytes());
+        mac
+    };
+    mac(expected)
+        .verify_slice(&mac(token).finalize().into_bytes())
+        .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    fn bearer(token: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            AUTHORIZATION,
+            HeaderValue::from_str(&format!("Bearer {token}")).unwrap(),
+        );
+        headers
+    }
+
+    #[test]
+    fn only_the_configured_token_authorizes() {
+        let token = MetricsToken(Some("secret".into()));
+        assert!(token.authorizes(&bearer("secret")));
+        assert!(!token.authorizes(&bearer("secres")));
+        assert!(!token.authorizes(&bearer("secret2")));
+        assert!(!token.authorizes(&bearer("")));
+        assert!(!token.authorizes(&HeaderMap::new()));
+    }
+
+    #[test]
+    fn unset_token_authorizes_nothing() {
+        assert!(!MetricsToken(None).authorizes(&bearer("")));
+    }
+}
@@ -0,0 +1,92 @@
+use std::env;
+use std::time::Duration;
+
+use chrono::Utc;
+
+use crate::prelude::*;
+
+/// Number of approved posts a user needs before their posts skip the approval queue.
+/// `None` disables the queue.
+#[derive(Clone, Copy)]
+pub struct ApprovalThreshold(pub Option<u64>);
+
+impl ApprovalThreshold {
+    /// Whether a new post by `author` should be published without review.
+    pub async fn approves(&self, db: &DatabaseConnection, author: &user::Model) -> Result<bool> {
+        match self.0 {
+            None => Ok(true),
+            Some(_) if author.moderator => Ok(true),
+            Some(threshold) => Ok(db.count_approved_posts_by(author.id).await? >= threshold),
+        }
+    }
+}
+
+/// Most posts a thread may hold, counting its opening post, from
+/// `LUNACHAT_MAX_POSTS_PER_THREAD`. `None` (or `0`) leaves threads unbounded.
+#[derive(Clone, Copy)]
+pub struct MaxPostsPerThread(pub Option<u64>);
+
+impl MaxPostsPerThread {
+    pub fn from_env() -> Result<Self> {
+        Ok(Self(match env::var("LUNACHAT_MAX_POSTS_PER_THREAD") {
+            Ok(max) => Some(max.parse()?).filter(|&max| max > 0),
+            Err(_) => None,
+        }))
+    }
+
+    /// Whether `thread_id` is too full for `author` to reply to. Moderators can always
+    /// reply.
+    pub async fn is_full(
+        &self,
+        db: &DatabaseConnection,
+        author: &user::Model,
+        thread_id: thread::Id,
+    ) -> Result<bool> {
+        match self.0 {
+            None => Ok(false),
+            Some(_) if author.moderator => Ok(false),
+            Some(max) => Ok(db.count_posts_in(thread_id).await? >= max),
+        }
+    }
+
+    pub fn message(&self) -> String {
+        format!(
+            "This thread has reached its limit of {} posts. Start a new thread to continue the conversation",
+            self.0.unwrap_or_default()
+        )
+    }
+}
+
+/// How old an account must be before it may start threads or reply, from
+/// `LUNACHAT_MIN_ACCOUNT_AGE_SECS`. `None` (or `0`) lets new accounts post right away.
+#[derive(Clone, Copy)]
+pub struct MinAccountAge(pub Option<Duration>);
+
+impl MinAccountAge {
+    pub fn from_env() -> Result<Self> {
+        Ok(Self(match env::var("LUNACHAT_MIN_ACCOUNT_AGE_SECS") {
+            Ok(secs) => Some(Duration::from_secs(secs.parse()?)).filter(|age| !age.is_zero()),
+            Err(_) => None,
+        }))
+    }
+
+    /// How much longer `author` has to wait before posting, if at all. Moderators and
+    /// accounts from before registration times were recorded never wait.
+    pub fn wait(&self, author: &user::Model) -> Option<Duration> {
+        let min_age = self.0?;
+        if author.moderator {
+            return None;
+        }
+        let age = (Utc::now() - author.created_at?)
+            .to_std()
+            .unwrap_or_default();
+        min_age.checked_sub(age).filter(|wait| !wait.is_zero())
+    }
+
+    pub fn message(wait: Duration) -> String {
+        format!(
+            "Your account is too new to post. Try again in {} seconds",
+            wait.as_secs().max(1)
+        )
+    }
+}
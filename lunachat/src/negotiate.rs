@@ -0,0 +1,128 @@
+use axum::Json;
+use axum::extract::FromRequestParts;
+use axum::http::HeaderValue;
+use axum::http::header::{ACCEPT, CACHE_CONTROL, VARY};
+use axum::http::request::Parts;
+use axum::response::{IntoResponse, Response};
+use serde::Serialize;
+
+use crate::prelude::*;
+
+/// Response representation picked from the request's `Accept` header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Html,
+    Json,
+}
+
+impl Format {
+    /// JSON is only chosen when the client asks for it with a higher quality than HTML,
+    /// so browsers and `*/*` clients keep getting pages.
+    pub fn from_accept(accept: &str) -> Self {
+        let mut html = None::<f32>;
+        let mut json = None::<f32>;
+        for range in accept.split(',') {
+            let mut params = range.split(';');
+            let media_type = params.next().unwrap_or_default().trim();
+            let quality = params
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|quality| quality.parse().ok())
+                .unwrap_or(1.0);
+            if media_type.eq_ignore_ascii_case("text/html") {
+                html = Some(html.map_or(quality, |html| html.max(quality)));
+            } else if media_type.eq_ignore_ascii_case("application/json") {
+                json = Some(json.map_or(quality, |json| json.max(quality)));
+            }
+        }
+        match (html, json) {
+            (None, Some(json)) if json > 0.0 => Format::Json,
+            (Some(html), Some(json)) if json > html => Format::Json,
+            _ => Format::Html,
+        }
+    }
+}
+
+impl<S> FromRequestParts<S> for Format
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self> {
+        Ok(parts
+            .headers
+            .get(ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+            .map_or(Format::Html, Format::from_accept))
+    }
+}
+
+/// Renders `html` as a page or `json` as a JSON body depending on `format`.
+///
+/// Both depend on the viewer as well as on `Accept`: pages show who's logged in and what
+/// they may do, and listings hide posts the viewer can't see. So responses vary on the
+/// session cookie too and are kept out of shared caches.
+pub struct Negotiated<T, D> {
+    pub format: Format,
+    pub html: T,
+    pub json: D,
+}
+
+impl<T, D> IntoResponse for Negotiated<T, D>
+where
+    HtmlTemplate<T>: IntoResponse,
+    D: Serialize,
+{
+    fn into_response(self) -> Response {
+        let mut response = match self.format {
+            Format::Html => HtmlTemplate(self.html).into_response(),
+            Format::Json => Json(self.json).into_response(),
+        };
+        let headers = response.headers_mut();
+        headers.insert(VARY, HeaderValue::from_static("Accept, Cookie"));
+        headers.insert(CACHE_CONTROL, HeaderValue::from_static("private"));
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn browsers_get_html() {
+        assert_eq!(
+            Format::from_accept("text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8"),
+            Format::Html
+        );
+        assert_eq!(Format::from_accept("*/*"), Format::Html);
+        assert_eq!(Format::from_accept(""), Format::Html);
+    }
+
+    #[test]
+    fn json_only_clients_get_json() {
+        assert_eq!(Format::from_accept("application/json"), Format::Json);
+        assert_eq!(Format::from_accept("Application/JSON"), Format::Json);
+    }
+
+    #[test]
+    fn higher_quality_wins() {
+        assert_eq!(
+            Format::from_accept("text/html;q=0.5, application/json"),
+            Format::Json
+        );
+        assert_eq!(
+            Format::from_accept("application/json;q=0.5, text/html"),
+            Format::Html
+        );
+    }
+
+    #[test]
+    fn ties_and_refusals_fall_back_to_html() {
+        assert_eq!(
+            Format::from_accept("application/json, text/html"),
+            Format::Html
+        );
+        assert_eq!(Format::from_accept("application/json;q=0"), Format::Html);
+    }
+}
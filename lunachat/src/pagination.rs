@@ -0,0 +1,177 @@
+use std::env;
+
+use axum::http::HeaderValue;
+use serde::Deserialize;
+
+use crate::prelude::*;
+
+/// Page sizes for paginated lists, from `LUNACHAT_PAGE_SIZE` (default 25) and
+/// `LUNACHAT_MAX_PAGE_SIZE` (default 100). Larger `per_page` requests are clamped.
+#[derive(Clone, Copy)]
+pub struct PageSize {
+    pub default: u64,
+    pub max: u64,
+}
+
+impl PageSize {
+    pub fn from_env() -> Result<Self> {
+        let max = match env::var("LUNACHAT_MAX_PAGE_SIZE") {
+            Ok(max) => max.parse()?,
+            Err(_) => 100,
+        };
+        let default = match env::var("LUNACHAT_PAGE_SIZE") {
+            Ok(default) => max.min(default.parse()?),
+            Err(_) => max.min(25),
+        };
+        if default == 0 {
+            return Err(anyhow!("LUNACHAT_PAGE_SIZE must be at least 1"));
+        }
+        Ok(Self { default, max })
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PageQuery {
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+}
+
+/// A zero-based page of a list.
+#[derive(Clone, Copy, Debug)]
+pub struct Page {
+    pub page: u64,
+    pub per_page: u64,
+}
+
+impl Page {
+    /// Validates `query`, clamping `per_page` to the configured maximum. Negative pages
+    /// and non-positive page sizes are refused.
+    pub fn from_query(query: PageQuery, size: PageSize) -> Result<Self, String> {
+        let page = match query.page {
+            Some(page) if page < 0 => return Err("page can't be negative".into()),
+            Some(page) => page as u64,
+            None => 0,
+        };
+        let per_page = match query.per_page {
+            Some(per_page) if per_page <= 0 => return Err("per_page must be at least 1".into()),
+            Some(per_page) => size.max.min(per_page as u64),
+            None => size.default,
+        };
+        Ok(Self { page, per_page })
+    }
+
+    pub fn offset(&self) -> u64 {
+        self.page.saturating_mul(self.per_page)
+    }
+
+    /// Index of the last page of a list with `total` items. An empty list still has page 0.
+    pub fn last(&self, total: u64) -> u64 {
+        total.div_ceil(self.per_page).saturating_sub(1)
+    }
+
+    /// The page after this one, if it has anything on it.
+    pub fn next(&self, total: u64) -> Option<u64> {
+        (self.page < self.last(total)).then_some(self.page + 1)
+    }
+
+    /// The page before this one. Pages past the end lead back to the last page.
+    pub fn prev(&self, total: u64) -> Option<u64> {
+        (self.page > 0).then(|| (self.page - 1).min(self.last(total)))
+    }
+
+    /// Where `page` of `path` is, at this page's size.
+    pub fn url(&self, path: &str, page: u64) -> String {
+        format!(
+            "{}?page={page}&per_page={}",
+            crate::url(path),
+            self.per_page
+        )
+    }
+
+    /// An RFC 8288 `Link` header pointing at the next, previous and last pages of `path`.
+    pub fn link_header(&self, path: &str, total: u64) -> Result<HeaderValue> {
+        let link = |page: u64, rel: &str| format!("<{}>; rel=\"{rel}\"", self.url(path, page));
+
+        let mut links = Vec::new();
+        if let Some(next) = self.next(total) {
+            links.push(link(next, "next"));
+        }
+        if let Some(prev) = self.prev(total) {
+            links.push(link(prev, "prev"));
+        }
+        links.push(link(self.last(total), "last"));
+        Ok(HeaderValue::from_str(&links.join(", "))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(page: u64, per_page: u64) -> Page {
+        Page { page, per_page }
+    }
+
+    fn links(page: Page, total: u64) -> String {
+        let header = page.link_header("/api/threads", total).unwrap();
+        header
+            .to_str()
+            .unwrap()
+            .replace(&crate::url("/api/threads"), "")
+    }
+
+    #[test]
+    fn first_page_links_next_and_last() {
+        assert_eq!(
+            links(page(0, 10), 25),
+            r#"<?page=1&per_page=10>; rel="next", <?page=2&per_page=10>; rel="last""#
+        );
+    }
+
+    #[test]
+    fn middle_page_links_both_ways() {
+        assert_eq!(
+            links(page(1, 10), 25),
+            r#"<?page=2&per_page=10>; rel="next", <?page=0&per_page=10>; rel="prev", <?page=2&per_page=10>; rel="last""#
+        );
+    }
+
+    #[test]
+    fn last_page_has_no_next() {
+        assert_eq!(
+            links(page(2, 10), 25),
+            r#"<?page=1&per_page=10>; rel="prev", <?page=2&per_page=10>; rel="last""#
+        );
+    }
+
+    #[test]
+    fn page_past_the_end_points_back_to_the_last() {
+        assert_eq!(
+            links(page(7, 10), 25),
+            r#"<?page=2&per_page=10>; rel="prev", <?page=2&per_page=10>; rel="last""#
+        );
+    }
+
+    #[test]
+    fn empty_list_has_only_page_zero() {
+        assert_eq!(
+            links(page(0, 10), 0),
+            r#"<?page=0&per_page=10>; rel="last""#
+        );
+    }
+
+    #[test]
+    fn per_page_is_clamped_and_validated() {
+        let size = PageSize {
+            default: 25,
+            max: 100,
+        };
+        let query = |page, per_page| PageQuery { page, per_page };
+        let clamped = Page::from_query(query(Some(3), Some(500)), size).unwrap();
+        assert_eq!((clamped.page, clamped.per_page), (3, 100));
+        let default = Page::from_query(query(None, None), size).unwrap();
+        assert_eq!((default.page, default.per_page), (0, 25));
+        assert!(Page::from_query(query(Some(-1), None), size).is_err());
+        assert!(Page::from_query(query(None, Some(0)), size).is_err());
+    }
+}
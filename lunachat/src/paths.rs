@@ -0,0 +1,70 @@
+use std::env;
+
+use lazy_static::lazy_static;
+
+lazy_static! {
+    static ref BASE_PATH: String = normalize(&env::var("LUNACHAT_BASE_PATH").unwrap_or_default());
+    static ref BASE_URL: String = env::var("LUNACHAT_BASE_URL")
+        .map(|base_url| base_url.trim().trim_end_matches('/').to_string())
+        .unwrap_or_else(|_| "http://localhost".into());
+}
+
+fn normalize(base_path: &str) -> String {
+    let base_path = base_path.trim().trim_matches('/');
+    if base_path.is_empty() {
+        String::new()
+    } else {
+        format!("/{base_path}")
+    }
+}
+
+/// The path the app is mounted under, from `LUNACHAT_BASE_PATH`, without a trailing slash.
+/// Empty when mounted at the root.
+pub fn base_path() -> &'static str {
+    &BASE_PATH
+}
+
+/// Prefixes an absolute app path with the base path.
+pub fn url(path: &str) -> String {
+    join(base_path(), path)
+}
+
+fn join(base_path: &str, path: &str) -> String {
+    match (base_path, path) {
+        ("", path) => path.to_string(),
+        (base_path, "/") => base_path.to_string(),
+        (base_path, path) => format!("{base_path}{path}"),
+    }
+}
+
+/// Like [`url`], but with the scheme and host from `LUNACHAT_BASE_URL`, for links that
+/// leave the site such as feed entries.
+pub fn absolute_url(path: &str) -> String {
+    format!("{}{}", *BASE_URL, url(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base_paths_are_normalized() {
+        assert_eq!(normalize(""), "");
+        assert_eq!(normalize("/"), "");
+        assert_eq!(normalize("/forum/"), "/forum");
+        assert_eq!(normalize("forum"), "/forum");
+        assert_eq!(normalize(" /community/forum/ "), "/community/forum");
+    }
+
+    #[test]
+    fn paths_at_the_root_are_unchanged() {
+        assert_eq!(join("", "/"), "/");
+        assert_eq!(join("", "/thread/3"), "/thread/3");
+    }
+
+    #[test]
+    fn paths_under_a_base_path_are_prefixed() {
+        assert_eq!(join("/forum", "/"), "/forum");
+        assert_eq!(join("/forum", "/thread/3"), "/forum/thread/3");
+    }
+}
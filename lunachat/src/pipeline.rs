@@ -0,0 +1,147 @@
+use std::env;
+use std::sync::Arc;
+
+use crate::emoji;
+use crate::prelude::*;
+use crate::sanitizer::Sanitizer;
+use crate::unicode::TextNormalizer;
+
+/// One step in turning a submitted body into the HTML that gets stored.
+pub trait BodyTransform: Send + Sync {
+    fn transform(&self, html: &str) -> Result<String>;
+}
+
+impl BodyTransform for TextNormalizer {
+    fn transform(&self, html: &str) -> Result<String> {
+        Ok(self.apply(html))
+    }
+}
+
+/// Expands `:shortcode:`s into emoji. Only touches text, so it's safe after sanitizing.
+struct Emoji;
+
+impl BodyTransform for Emoji {
+    fn transform(&self, html: &str) -> Result<String> {
+        Ok(emoji::expand(html))
+    }
+}
+
+/// The sanitizer as a stage. Only [`BodyPipeline`] can make one, so it's always there.
+struct Sanitize(Sanitizer);
+
+impl BodyTransform for Sanitize {
+    fn transform(&self, html: &str) -> Result<String> {
+        Ok(self.0.clean_with_links(html))
+    }
+}
+
+/// The transforms every post body goes through, in order.
+///
+/// The order comes from the comma-separated `LUNACHAT_BODY_TRANSFORMS`, naming stages
+/// from `normalize`, `sanitize` and `emoji`, and defaults to `sanitize,normalize`. Emoji
+/// shortcodes are only expanded when `emoji` is named. Sanitizing can't
+/// be left out: if it isn't named it runs last. Stages after it are trusted to keep the
+/// HTML safe.
+///
+/// Word filtering and mention linking run after the pipeline, since one rejects bodies
+/// rather than transforming them and the other looks users up.
+#[derive(Clone)]
+pub struct BodyPipeline {
+    stages: Arc<Vec<Box<dyn BodyTransform>>>,
+}
+
+impl BodyPipeline {
+    pub fn from_env(sanitizer: Sanitizer, normalizer: TextNormalizer) -> Result<Self> {
+        let names =
+            env::var("LUNACHAT_BODY_TRANSFORMS").unwrap_or_else(|_| "sanitize,normalize".into());
+        Self::new(
+            names
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty()),
+            sanitizer,
+            normalizer,
+        )
+    }
+
+    pub fn new<'a>(
+        names: impl IntoIterator<Item = &'a str>,
+        sanitizer: Sanitizer,
+        normalizer: TextNormalizer,
+    ) -> Result<Self> {
+        let mut stages = Vec::<Box<dyn BodyTransform>>::new();
+        let mut sanitized = false;
+        for name in names {
+            match name {
+                "sanitize" => {
+                    stages.push(Box::new(Sanitize(sanitizer.clone())));
+                    sanitized = true;
+                }
+                "normalize" => stages.push(Box::new(normalizer)),
+                "emoji" => stages.push(Box::new(Emoji)),
+                name => return Err(anyhow!("Unknown body transform {name:?}")),
+            }
+        }
+        if !sanitized {
+            stages.push(Box::new(Sanitize(sanitizer)));
+        }
+        Ok(Self {
+            stages: Arc::new(stages),
+        })
+    }
+
+    pub fn apply(&self, body: &str) -> Result<String> {
+        self.stages
+            .iter()
+            .try_fold(body.to_string(), |html, stage| stage.transform(&html))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sanitizer::{ImagePolicy, TrackingParams};
+
+    fn pipeline(names: &[&str]) -> Result<BodyPipeline> {
+        let sanitizer = Sanitizer::new(|_| {}, ImagePolicy::default(), TrackingParams::new([]))?;
+        BodyPipeline::new(
+            names.iter().copied(),
+            sanitizer,
+            TextNormalizer { enabled: true },
+        )
+    }
+
+    #[test]
+    fn sanitize_runs_last_when_not_named() {
+        let pipeline = pipeline(&["normalize", "emoji"]).unwrap();
+        assert_eq!(
+            pipeline
+                .apply("<b onclick=\"steal()\">:fire:</b><script>x</script>a\u{200B}b")
+                .unwrap(),
+            "<b>🔥</b>ab"
+        );
+    }
+
+    #[test]
+    fn nothing_named_still_sanitizes() {
+        let pipeline = pipeline(&[]).unwrap();
+        assert_eq!(
+            pipeline.apply("<p>hi<script>x</script></p>").unwrap(),
+            "<p>hi</p>"
+        );
+    }
+
+    #[test]
+    fn named_order_is_kept() {
+        let pipeline = pipeline(&["sanitize", "emoji"]).unwrap();
+        assert_eq!(
+            pipeline.apply("<p>:fire:</p><code>:fire:</code>").unwrap(),
+            "<p>🔥</p><code>:fire:</code>"
+        );
+    }
+
+    #[test]
+    fn unknown_stages_are_refused() {
+        assert!(pipeline(&["sanitize", "markdown"]).is_err());
+    }
+}
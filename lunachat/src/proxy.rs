@@ -0,0 +1,189 @@
+use std::env;
+use std::net::{IpAddr, SocketAddr};
+
+use axum::extract::{ConnectInfo, FromRequestParts};
+use axum::http::HeaderMap;
+use axum::http::request::Parts;
+use axum::{Extension, RequestPartsExt as _};
+
+use crate::prelude::*;
+
+/// A network written as `address/prefix`, or a bare address.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Network {
+    addr: IpAddr,
+    prefix: u32,
+}
+
+impl Network {
+    fn parse(network: &str) -> Result<Self> {
+        let (addr, prefix) = match network.split_once('/') {
+            Some((addr, prefix)) => (addr.parse::<IpAddr>()?, Some(prefix.parse::<u32>()?)),
+            None => (network.parse::<IpAddr>()?, None),
+        };
+        let bits = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.unwrap_or(bits);
+        if prefix > bits {
+            return Err(anyhow!("Prefix too long in {network:?}"));
+        }
+        Ok(Self { addr, prefix })
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Reverse proxies whose `X-Forwarded-For` is believed, from the comma-separated
+/// `LUNACHAT_TRUSTED_PROXIES` of addresses or `address/prefix` networks. Unset trusts no
+/// one, and every request is attributed to its peer address.
+#[derive(Clone, Debug, Default)]
+pub struct TrustedProxies {
+    networks: Vec<Network>,
+}
+
+impl TrustedProxies {
+    pub fn from_env() -> Result<Self> {
+        Self::new(
+            env::var("LUNACHAT_TRUSTED_PROXIES")
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|network| !network.is_empty()),
+        )
+    }
+
+    pub fn new<'a>(networks: impl IntoIterator<Item = &'a str>) -> Result<Self> {
+        Ok(Self {
+            networks: networks
+                .into_iter()
+                .map(Network::parse)
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    fn trusts(&self, ip: IpAddr) -> bool {
+        self.networks.iter().any(|network| network.contains(ip))
+    }
+
+    /// The address a request really came from. `X-Forwarded-For` is walked from the
+    /// right, since each trusted proxy appends the address it saw, and the first hop
+    /// that isn't a trusted proxy is the client. Anything left of that could be forged.
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        if !self.trusts(peer) {
+            return peer;
+        }
+        let mut client = peer;
+        let hops = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .collect::<Vec<_>>();
+        for hop in hops.into_iter().rev() {
+            let Ok(hop) = hop.trim().parse::<IpAddr>() else {
+                break;
+            };
+            client = hop;
+            if !self.trusts(hop) {
+                break;
+            }
+        }
+        client
+    }
+}
+
+/// The client's address, seen through any [`TrustedProxies`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ClientIp(pub IpAddr);
+
+impl<S> FromRequestParts<S> for ClientIp
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self> {
+        let ConnectInfo(addr) = parts.extract::<ConnectInfo<SocketAddr>>().await?;
+        let Extension(proxies) = parts.extract::<Extension<TrustedProxies>>().await?;
+
+        Ok(ClientIp(proxies.client_ip(addr.ip(), &parts.headers)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    fn forwarded(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn untrusted_peer_is_the_client() {
+        let proxies = TrustedProxies::new(["10.0.0.1"]).unwrap();
+        assert_eq!(
+            proxies.client_ip(ip("192.0.2.7"), &forwarded("198.51.100.1")),
+            ip("192.0.2.7")
+        );
+    }
+
+    #[test]
+    fn trusted_peer_forwards_the_client() {
+        let proxies = TrustedProxies::new(["172.16.0.0/12"]).unwrap();
+        assert_eq!(
+            proxies.client_ip(ip("172.18.0.5"), &forwarded("198.51.100.1")),
+            ip("198.51.100.1")
+        );
+    }
+
+    #[test]
+    fn forged_hops_left_of_the_client_are_ignored() {
+        let proxies = TrustedProxies::new(["10.0.0.0/8"]).unwrap();
+        assert_eq!(
+            proxies.client_ip(
+                ip("10.0.0.2"),
+                &forwarded("203.0.113.9, 198.51.100.1, 10.0.0.3")
+            ),
+            ip("198.51.100.1")
+        );
+    }
+
+    #[test]
+    fn missing_or_garbled_header_falls_back_to_the_peer() {
+        let proxies = TrustedProxies::new(["10.0.0.1"]).unwrap();
+        assert_eq!(
+            proxies.client_ip(ip("10.0.0.1"), &HeaderMap::new()),
+            ip("10.0.0.1")
+        );
+        assert_eq!(
+            proxies.client_ip(ip("10.0.0.1"), &forwarded("not an address")),
+            ip("10.0.0.1")
+        );
+    }
+
+    #[test]
+    fn invalid_networks_are_refused() {
+        assert!(TrustedProxies::new(["10.0.0.0/33"]).is_err());
+        assert!(TrustedProxies::new(["proxy"]).is_err());
+    }
+}
@@ -0,0 +1,206 @@
+use std::collections::HashSet;
+use std::env;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use lru::LruCache;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::prelude::*;
+use crate::timezone::ViewerTimezone;
+
+/// Cache key for a thread's rendered posts: the thread, whether signatures are shown and
+/// the timezone timestamps are rendered in.
+pub type Key = (thread::Id, bool, ViewerTimezone);
+
+/// Rendered post lists of recently viewed threads.
+///
+/// Entries are tagged with a [`version`](Self::version) of the thread they were rendered
+/// from, which is cheap to look up, so a hit doesn't need the posts loaded at all and new
+/// or approved posts miss the cache on their own. Author changes such as a new signature
+/// don't show up in the version, so they drop the entries of threads the author has posts
+/// in. Only renders that every viewer would see identically may be stored.
+#[derive(Clone, Default)]
+pub struct RenderCache {
+    entries: Option<Arc<Mutex<LruCache<Key, Entry>>>>,
+}
+
+struct Entry {
+    version: u64,
+    html: String,
+    authors: HashSet<user::Id>,
+}
+
+impl RenderCache {
+    /// Holds `LUNACHAT_RENDER_CACHE_SIZE` threads, 128 by default. `0` disables caching.
+    pub fn from_env() -> Result<Self> {
+        let size = match env::var("LUNACHAT_RENDER_CACHE_SIZE") {
+            Ok(size) => size.parse()?,
+            Err(_) => 128,
+        };
+        let Some(size) = NonZeroUsize::new(size) else {
+            return Ok(Self::default());
+        };
+        let cache = Self::new(size);
+        cache.forget_changed_authors();
+        Ok(cache)
+    }
+
+    fn new(size: NonZeroUsize) -> Self {
+        Self {
+            entries: Some(Arc::new(Mutex::new(LruCache::new(size)))),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.entries.is_some()
+    }
+
+    /// Drops the entries of threads with posts by users who change. Deleting an account
+    /// doesn't say whose it was, so that clears everything, as does missing events.
+    fn forget_changed_authors(&self) {
+        let Some(entries) = &self.entries else {
+            return;
+        };
+        let mut sub = user::BROADCAST.subscribe();
+        let weak = Arc::downgrade(entries);
+        tokio::spawn(async move {
+            loop {
+                let changed = match sub.recv().await {
+                    Ok(BroadcastEvent::Update(user)) => Some(user.id),
+                    // A new account has no posts yet.
+                    Ok(BroadcastEvent::Create(_)) => continue,
+                    Ok(BroadcastEvent::Delete) | Err(RecvError::Lagged(_)) => None,
+                    Err(RecvError::Closed) => break,
+                };
+                let Some(entries) = weak.upgrade() else {
+                    break;
+                };
+                let cache = Self {
+                    entries: Some(entries),
+                };
+                match changed {
+                    Some(user_id) => cache.forget_author(user_id),
+                    None => cache.clear(),
+                }
+            }
+        });
+    }
+
+    fn lock(&self) -> Option<MutexGuard<'_, LruCache<Key, Entry>>> {
+        self.entries
+            .as_ref()
+            .map(|entries| entries.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    fn forget_author(&self, user_id: user::Id) {
+        let Some(mut entries) = self.lock() else {
+            return;
+        };
+        let stale = entries
+            .iter()
+            .filter(|(_, entry)| entry.authors.contains(&user_id))
+            .map(|(key, _)| *key)
+            .collect::<Vec<_>>();
+        for key in stale {
+            entries.pop(&key);
+        }
+    }
+
+    fn clear(&self) {
+        if let Some(mut entries) = self.lock() {
+            entries.clear();
+        }
+    }
+
+    /// Fingerprint of everything about `thread` that changes how its posts render, from
+    /// the thread itself and the [public post summary] of it. Public posts are never
+    /// hidden or edited, so the summary changes whenever the set of them does.
+    ///
+    /// [public post summary]: crate::entity::DatabaseConnectionExt::summarize_public_posts_in
+    pub fn version(thread: &thread::Model, public_posts: (u64, Option<post::Id>)) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        thread.accepted_answer.hash(&mut hasher);
+        public_posts.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// The stored render for `key`, if it was rendered from this `version`.
+    pub fn get(&self, key: &Key, version: u64) -> Option<String> {
+        let mut entries = self.lock()?;
+        entries
+            .get(key)
+            .filter(|entry| entry.version == version)
+            .map(|entry| entry.html.clone())
+    }
+
+    /// Stores `html` for `key`, to be dropped if any of its `authors` change.
+    pub fn insert(
+        &self,
+        key: Key,
+        version: u64,
+        html: String,
+        authors: impl IntoIterator<Item = user::Id>,
+    ) {
+        let Some(mut entries) = self.lock() else {
+            return;
+        };
+        entries.put(
+            key,
+            Entry {
+                version,
+                html,
+                authors: authors.into_iter().collect(),
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache() -> RenderCache {
+        RenderCache::new(NonZeroUsize::new(4).unwrap())
+    }
+
+    fn key(id: i64) -> Key {
+        (
+            serde_json::from_value(id.into()).unwrap(),
+            true,
+            ViewerTimezone::default(),
+        )
+    }
+
+    fn user(id: i64) -> user::Id {
+        serde_json::from_value(id.into()).unwrap()
+    }
+
+    #[test]
+    fn hit_needs_the_same_version() {
+        let cache = cache();
+        assert_eq!(cache.get(&key(1), 7), None);
+        cache.insert(key(1), 7, "posts".into(), [user(1)]);
+        assert_eq!(cache.get(&key(1), 7).as_deref(), Some("posts"));
+        assert_eq!(cache.get(&key(1), 8), None);
+        assert_eq!(cache.get(&key(2), 7), None);
+    }
+
+    #[test]
+    fn changed_author_only_drops_their_threads() {
+        let cache = cache();
+        cache.insert(key(1), 1, "one".into(), [user(1), user(2)]);
+        cache.insert(key(2), 1, "two".into(), [user(3)]);
+        cache.forget_author(user(2));
+        assert_eq!(cache.get(&key(1), 1), None);
+        assert_eq!(cache.get(&key(2), 1).as_deref(), Some("two"));
+    }
+
+    #[test]
+    fn disabled_cache_stores_nothing() {
+        let cache = RenderCache::default();
+        cache.insert(key(1), 1, "one".into(), []);
+        assert_eq!(cache.get(&key(1), 1), None);
+    }
+}
@@ -0,0 +1,54 @@
+use std::env;
+use std::fmt::Write as _;
+
+use crate::auth::public_read;
+use crate::prelude::*;
+
+/// Paths crawlers are kept out of by default: forms, the API, and SSE streams that never
+/// finish loading.
+const DEFAULT_DISALLOW: &[&str] = &[
+    "/login",
+    "/register",
+    "/logout",
+    "/admin/",
+    "/api/",
+    "/sse",
+    "/thread/*/sse",
+    "/preview",
+];
+
+/// Crawl policy served at `/robots.txt`.
+#[derive(Clone)]
+pub struct RobotsPolicy {
+    pub disallow: Vec<String>,
+}
+
+impl RobotsPolicy {
+    /// Reads the comma-separated `LUNACHAT_ROBOTS_DISALLOW`, falling back to
+    /// [`DEFAULT_DISALLOW`]. A private instance (see [`public_read`]) disallows everything.
+    pub fn from_env() -> Result<Self> {
+        let disallow = if !public_read()? {
+            vec!["/".into()]
+        } else if let Ok(disallow) = env::var("LUNACHAT_ROBOTS_DISALLOW") {
+            disallow
+                .split(',')
+                .map(str::trim)
+                .filter(|path| !path.is_empty())
+                .map(String::from)
+                .collect()
+        } else {
+            DEFAULT_DISALLOW.iter().map(|&path| path.into()).collect()
+        };
+        Ok(Self { disallow })
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::from("User-agent: *\n");
+        for path in &self.disallow {
+            let _ = writeln!(out, "Disallow: {}", crate::url(path));
+        }
+        // Search engines accept Atom feeds as sitemaps.
+        let _ = writeln!(out, "\nSitemap: {}", crate::absolute_url("/feed.xml"));
+        out
+    }
+}
@@ -1,12 +1,447 @@
+use std::collections::{BTreeMap, HashSet};
+use std::env;
 use std::sync::Arc;
 
 use derive_more::{Deref, DerefMut};
+use itertools::Itertools as _;
+use serde::Serialize;
+use url::Url;
+
+use crate::prelude::*;
 
 #[derive(Clone, Deref, DerefMut)]
-pub struct Sanitizer(pub Arc<ammonia::Builder<'static>>);
+pub struct Sanitizer {
+    #[deref]
+    #[deref_mut]
+    builder: Arc<ammonia::Builder<'static>>,
+    images: Arc<ammonia::Builder<'static>>,
+}
 
 impl Sanitizer {
-    pub fn new(builder: ammonia::Builder<'static>) -> Self {
-        Self(Arc::new(builder))
+    /// `configure` is applied to both the base policy and the image-preserving policy, so
+    /// the two only differ in how they treat `<img>`. Both strip `tracking` parameters from
+    /// link targets. Policies that ammonia would refuse when cleaning are refused here.
+    pub fn new(
+        configure: impl Fn(&mut ammonia::Builder<'static>),
+        images: ImagePolicy,
+        tracking: TrackingParams,
+    ) -> Result<Self> {
+        let mut builder = ammonia::Builder::new();
+        configure(&mut builder);
+        let base_tracking = tracking.clone();
+        builder
+            .rm_tags(["img"])
+            .attribute_filter(move |element, attribute, value| {
+                if element == "a" && attribute == "href" {
+                    Some(base_tracking.strip(value).into())
+                } else {
+                    Some(value.into())
+                }
+            });
+        check_policy(&builder)?;
+
+        // ammonia only takes one attribute filter per builder, so this one handles both.
+        let mut image_builder = ammonia::Builder::new();
+        configure(&mut image_builder);
+        image_builder
+            .add_tags(["img"])
+            .add_tag_attributes("img", ["src", "alt", "width", "height"])
+            .attribute_filter(
+                move |element, attribute, value| match (element, attribute) {
+                    ("img", "src") if !images.allows(value) => None,
+                    ("a", "href") => Some(tracking.strip(value).into()),
+                    _ => Some(value.into()),
+                },
+            );
+        check_policy(&image_builder)?;
+
+        Ok(Self {
+            builder: Arc::new(builder),
+            images: Arc::new(image_builder),
+        })
+    }
+
+    /// Like [`clean`](ammonia::Builder::clean), but keeps `<img>` tags whose `src` passes
+    /// the [`ImagePolicy`].
+    pub fn clean_with_links(&self, body: &str) -> String {
+        self.images.clean(body).to_string()
+    }
+
+    /// What survives [`clean_with_links`](Self::clean_with_links), read back from the
+    /// policy itself so the help shown to posters always matches it.
+    pub fn formatting(&self) -> Formatting {
+        let sorted = |set: HashSet<&str>| -> Vec<String> {
+            set.into_iter().map(String::from).sorted().collect()
+        };
+        Formatting {
+            tags: sorted(self.images.clone_tags()),
+            generic_attributes: sorted(self.images.clone_generic_attributes()),
+            tag_attributes: self
+                .images
+                .clone_tag_attributes()
+                .into_iter()
+                .map(|(tag, attributes)| (tag.to_string(), sorted(attributes)))
+                .collect(),
+            url_schemes: sorted(self.images.clone_url_schemes()),
+        }
+    }
+}
+
+/// Rejects the policies ammonia would panic on at [`clean`](ammonia::Builder::clean)
+/// time, so a bad allowlist stops startup instead of every post submission.
+fn check_policy(builder: &ammonia::Builder) -> Result<()> {
+    let tags = builder.clone_tags();
+    let tag_attributes = builder.clone_tag_attributes();
+    let removed = builder.clone_clean_content_tags();
+    let conflicts = removed
+        .iter()
+        .filter(|tag| tags.contains(*tag) || tag_attributes.contains_key(*tag))
+        .sorted()
+        .join(", ");
+    if !conflicts.is_empty() {
+        return Err(anyhow!(
+            "{conflicts} can't be allowed: ammonia always removes their content"
+        ));
+    }
+
+    // ammonia sets `rel` on links itself, so it can't also be passed through.
+    if builder.clone_generic_attributes().contains("rel")
+        || tag_attributes
+            .get("a")
+            .is_some_and(|attributes| attributes.contains("rel"))
+    {
+        return Err(anyhow!(
+            "rel can't be an allowed attribute: links always get rel=\"noopener noreferrer\""
+        ));
+    }
+    Ok(())
+}
+
+/// Markup allowed in posts, from the environment:
+///
+/// - `LUNACHAT_ALLOWED_TAGS`: comma-separated tags, replacing ammonia's default list.
+/// - `LUNACHAT_ALLOWED_ATTRIBUTES`: comma-separated attributes allowed on every tag.
+///   Defaults to `style`; set it to an empty string to allow none.
+#[derive(Clone, Default)]
+pub struct SanitizerConfig {
+    pub tags: Option<HashSet<String>>,
+    pub generic_attributes: HashSet<String>,
+}
+
+impl SanitizerConfig {
+    pub fn from_env() -> Self {
+        let list = |list: String| -> HashSet<String> {
+            list.split(',')
+                .map(|item| item.trim().to_ascii_lowercase())
+                .filter(|item| !item.is_empty())
+                .collect()
+        };
+        Self {
+            tags: env::var("LUNACHAT_ALLOWED_TAGS").ok().map(list),
+            generic_attributes: env::var("LUNACHAT_ALLOWED_ATTRIBUTES")
+                .map(list)
+                .unwrap_or_else(|_| HashSet::from(["style".into()])),
+        }
+    }
+
+    /// ammonia borrows its allowlists for as long as the policy lives, which is the whole
+    /// process, so the config is kept for good too.
+    pub fn leak(self) -> &'static Self {
+        Box::leak(Box::new(self))
+    }
+
+    pub fn configure<'a>(&'a self, builder: &mut ammonia::Builder<'a>) {
+        if let Some(tags) = &self.tags {
+            builder.tags(tags.iter().map(String::as_str).collect());
+        }
+        builder.add_generic_attributes(self.generic_attributes.iter().map(String::as_str));
+    }
+}
+
+/// The allowlist posts are sanitized with, served at `/api/formatting` and shown as
+/// formatting help.
+#[derive(Clone, Serialize)]
+pub struct Formatting {
+    pub tags: Vec<String>,
+    /// Allowed on every tag.
+    pub generic_attributes: Vec<String>,
+    pub tag_attributes: BTreeMap<String, Vec<String>>,
+    pub url_schemes: Vec<String>,
+}
+
+#[derive(Clone, Default)]
+pub struct ImagePolicy {
+    /// Hosts images may be loaded from. `None` allows any https host.
+    pub allowed_hosts: Option<HashSet<String>>,
+}
+
+impl ImagePolicy {
+    /// Reads the host allowlist from the comma-separated `LUNACHAT_IMAGE_HOSTS`.
+    pub fn from_env() -> Self {
+        let allowed_hosts = env::var("LUNACHAT_IMAGE_HOSTS").ok().map(|hosts| {
+            hosts
+                .split(',')
+                .map(|host| host.trim().to_ascii_lowercase())
+                .filter(|host| !host.is_empty())
+                .collect()
+        });
+        Self { allowed_hosts }
+    }
+
+    pub fn allows(&self, src: &str) -> bool {
+        let Ok(url) = Url::parse(src) else {
+            return false;
+        };
+        if url.scheme() != "https" {
+            return false;
+        }
+        let Some(host) = url.host_str() else {
+            return false;
+        };
+        match &self.allowed_hosts {
+            Some(hosts) => hosts.contains(host),
+            None => true,
+        }
+    }
+}
+
+/// Query parameters that only exist to track who clicked a link.
+const DEFAULT_TRACKING_PARAMS: &[&str] = &[
+    "utm_*", "fbclid", "gclid", "dclid", "msclkid", "mc_cid", "mc_eid", "igshid", "yclid",
+    "_hsenc", "_hsmi",
+];
+
+/// Tracking parameters removed from links in posts, from the comma-separated
+/// `LUNACHAT_STRIP_PARAMS` or [`DEFAULT_TRACKING_PARAMS`]. A trailing `*` matches any
+/// parameter with that prefix. Set it to an empty string to keep links as they are.
+#[derive(Clone)]
+pub struct TrackingParams {
+    params: Arc<Vec<String>>,
+}
+
+impl TrackingParams {
+    pub fn from_env() -> Self {
+        match env::var("LUNACHAT_STRIP_PARAMS") {
+            Ok(params) => Self::new(params.split(',')),
+            Err(_) => Self::new(DEFAULT_TRACKING_PARAMS.iter().copied()),
+        }
+    }
+
+    pub fn new<'a>(params: impl IntoIterator<Item = &'a str>) -> Self {
+        Self {
+            params: Arc::new(
+                params
+                    .into_iter()
+                    .map(|param| param.trim().to_ascii_lowercase())
+                    .filter(|param| !param.is_empty())
+                    .collect(),
+            ),
+        }
+    }
+
+    fn is_tracking(&self, name: &str) -> bool {
+        let name = name.to_ascii_lowercase();
+        self.params
+            .iter()
+            .any(|param| match param.strip_suffix('*') {
+                Some(prefix) => name.starts_with(prefix),
+                None => name == *param,
+            })
+    }
+
+    /// Removes tracking parameters from `href`. Anything that isn't an absolute URL, or
+    /// has nothing to remove, comes back unchanged.
+    pub fn strip(&self, href: &str) -> String {
+        let Ok(mut url) = Url::parse(href) else {
+            return href.to_string();
+        };
+        if !url.query_pairs().any(|(name, _)| self.is_tracking(&name)) {
+            return href.to_string();
+        }
+
+        let kept = url
+            .query_pairs()
+            .filter(|(name, _)| !self.is_tracking(name))
+            .map(|(name, value)| (name.into_owned(), value.into_owned()))
+            .collect::<Vec<_>>();
+        if kept.is_empty() {
+            url.set_query(None);
+        } else {
+            url.query_pairs_mut().clear().extend_pairs(kept);
+        }
+        url.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sanitizer(config: SanitizerConfig) -> Result<Sanitizer> {
+        let config = config.leak();
+        Sanitizer::new(
+            |builder| config.configure(builder),
+            ImagePolicy::default(),
+            tracking(&[]),
+        )
+    }
+
+    fn list(items: &[&str]) -> HashSet<String> {
+        items.iter().map(|&item| item.into()).collect()
+    }
+
+    #[test]
+    fn tags_whose_content_is_removed_are_refused() {
+        for tag in ["script", "style"] {
+            let config = SanitizerConfig {
+                tags: Some(list(&["p", tag])),
+                ..Default::default()
+            };
+            assert!(sanitizer(config).is_err(), "{tag} was accepted");
+        }
+    }
+
+    #[test]
+    fn rel_attribute_is_refused() {
+        let config = SanitizerConfig {
+            tags: None,
+            generic_attributes: list(&["rel"]),
+        };
+        assert!(sanitizer(config).is_err());
+    }
+
+    #[test]
+    fn formatting_reflects_the_config() {
+        let config = SanitizerConfig {
+            tags: Some(list(&["p", "b", "a"])),
+            generic_attributes: list(&[]),
+        };
+        let formatting = sanitizer(config).unwrap().formatting();
+        assert!(!formatting.generic_attributes.contains(&"style".to_string()));
+        assert!(formatting.tags.contains(&"b".to_string()));
+        assert!(!formatting.tags.contains(&"i".to_string()));
+    }
+
+    fn with_images(allowed_hosts: Option<&[&str]>) -> Sanitizer {
+        Sanitizer::new(
+            |_| {},
+            ImagePolicy {
+                allowed_hosts: allowed_hosts.map(list),
+            },
+            tracking(&[]),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn image_handlers_are_stripped() {
+        let cleaned = with_images(None)
+            .clean_with_links(r#"<img src="https://img.example/a.png" onerror="alert(1)">"#);
+        assert_eq!(cleaned, r#"<img src="https://img.example/a.png">"#);
+        assert!(
+            !with_images(None)
+                .clean_with_links("<img src=x onerror=alert(1)>")
+                .contains("onerror")
+        );
+    }
+
+    #[test]
+    fn allowlisted_https_images_are_kept() {
+        let sanitizer = with_images(Some(&["img.example"]));
+        assert_eq!(
+            sanitizer.clean_with_links(r#"<img src="https://img.example/a.png" alt="a">"#),
+            r#"<img src="https://img.example/a.png" alt="a">"#
+        );
+    }
+
+    #[test]
+    fn http_and_other_hosts_lose_their_src() {
+        let sanitizer = with_images(Some(&["img.example"]));
+        for src in [
+            "http://img.example/a.png",
+            "https://elsewhere.example/a.png",
+        ] {
+            let cleaned = sanitizer.clean_with_links(&format!(r#"<img src="{src}">"#));
+            assert!(!cleaned.contains(src), "{src} survived: {cleaned}");
+        }
+    }
+
+    #[test]
+    fn base_policy_drops_images() {
+        assert_eq!(
+            with_images(None)
+                .clean(r#"<img src="https://img.example/a.png">"#)
+                .to_string(),
+            ""
+        );
+    }
+
+    #[test]
+    fn default_config_is_accepted() {
+        let config = SanitizerConfig {
+            tags: None,
+            generic_attributes: list(&["style"]),
+        };
+        let sanitizer = sanitizer(config).unwrap();
+        assert_eq!(
+            sanitizer
+                .clean(r#"<p style="color: red">hi</p>"#)
+                .to_string(),
+            r#"<p style="color: red">hi</p>"#
+        );
+    }
+
+    fn tracking(params: &[&str]) -> TrackingParams {
+        TrackingParams {
+            params: Arc::new(params.iter().map(|&param| param.into()).collect()),
+        }
+    }
+
+    #[test]
+    fn tracking_params_are_stripped() {
+        let tracking = tracking(DEFAULT_TRACKING_PARAMS);
+        assert_eq!(
+            tracking.strip("https://example.com/a?id=1&utm_source=x&FBCLID=y"),
+            "https://example.com/a?id=1"
+        );
+    }
+
+    #[test]
+    fn query_is_dropped_once_empty() {
+        let tracking = tracking(DEFAULT_TRACKING_PARAMS);
+        assert_eq!(
+            tracking.strip("https://example.com/a?utm_medium=x&gclid=y#top"),
+            "https://example.com/a#top"
+        );
+    }
+
+    #[test]
+    fn links_without_tracking_are_unchanged() {
+        let tracking = tracking(DEFAULT_TRACKING_PARAMS);
+        for href in [
+            "https://example.com/a?q=a+b&utm=kept",
+            "/relative?utm_source=x",
+            "not a url",
+        ] {
+            assert_eq!(tracking.strip(href), href);
+        }
+    }
+
+    #[test]
+    fn empty_list_keeps_everything() {
+        let href = "https://example.com/a?utm_source=x";
+        assert_eq!(tracking(&[]).strip(href), href);
+    }
+
+    #[test]
+    fn cleaned_links_lose_tracking_params() {
+        let sanitizer =
+            Sanitizer::new(|_| {}, ImagePolicy::default(), tracking(&["utm_*"])).unwrap();
+        assert_eq!(
+            sanitizer
+                .clean(r#"<a href="https://example.com/?utm_source=x">a</a>"#)
+                .to_string(),
+            r#"<a href="https://example.com/" rel="noopener noreferrer">a</a>"#
+        );
     }
 }
@@ -0,0 +1,111 @@
+use std::env;
+
+use axum::extract::{Request, State};
+use axum::http::HeaderValue;
+use axum::http::header::{
+    CONTENT_SECURITY_POLICY, REFERRER_POLICY, X_CONTENT_TYPE_OPTIONS, X_FRAME_OPTIONS,
+};
+use axum::middleware::Next;
+use axum::response::Response;
+
+use crate::prelude::*;
+
+/// The policy used when `LUNACHAT_CSP` isn't set.
+///
+/// Scripts are only loaded from `/static`, but htmx evaluates `hx-on` attributes, which
+/// needs `'unsafe-eval'`, and the avatar fallback is an inline `onerror` handler allowed
+/// by its hash. Any other inline `on*` handler is blocked, so templates use `hx-on`
+/// instead. Inline styles are allowed because the sanitizer keeps `style` attributes.
+/// Images may come from any https host, matching the sanitizer's image policy and
+/// off-site avatars. SSE streams are same-origin, so `connect-src 'self'` covers them.
+const DEFAULT_CSP: &str = "default-src 'self'; \
+    script-src 'self' 'unsafe-eval' 'unsafe-hashes' 'sha256-pwQduWxX3Cm7lLbQmAos7n73RLP4QrsCDFejmsBdZ78='; \
+    style-src 'self' 'unsafe-inline'; \
+    img-src 'self' https: data:; \
+    connect-src 'self'; \
+    frame-ancestors 'none'; \
+    base-uri 'self'; \
+    form-action 'self'";
+
+/// Headers added to every response as defense in depth for user-submitted HTML.
+#[derive(Clone)]
+pub struct SecurityHeaders {
+    pub content_security_policy: HeaderValue,
+}
+
+impl SecurityHeaders {
+    /// Reads the whole `Content-Security-Policy` from `LUNACHAT_CSP`, falling back to
+    /// [`DEFAULT_CSP`]. Instances that embed images or avatars from elsewhere can extend
+    /// the default from there.
+    pub fn from_env() -> Result<Self> {
+        let content_security_policy = match env::var("LUNACHAT_CSP") {
+            Ok(csp) => HeaderValue::from_str(csp.trim())?,
+            Err(_) => HeaderValue::from_static(DEFAULT_CSP),
+        };
+        Ok(Self {
+            content_security_policy,
+        })
+    }
+}
+
+/// Sets the security headers on the response, leaving any a handler already set alone.
+pub async fn apply(State(security): State<SecurityHeaders>, req: Request, next: Next) -> Response {
+    let mut response = next.run(req).await;
+
+    let headers = response.headers_mut();
+    headers
+        .entry(CONTENT_SECURITY_POLICY)
+        .or_insert(security.content_security_policy);
+    headers
+        .entry(X_CONTENT_TYPE_OPTIONS)
+        .or_insert(HeaderValue::from_static("nosniff"));
+    headers
+        .entry(REFERRER_POLICY)
+        .or_insert(HeaderValue::from_static("strict-origin-when-cross-origin"));
+    headers
+        .entry(X_FRAME_OPTIONS)
+        .or_insert(HeaderValue::from_static("DENY"));
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::Router;
+    use axum::body::Body;
+    use axum::http::{HeaderMap, Request};
+    use axum::routing::get;
+    use tower::ServiceExt as _;
+
+    use super::*;
+
+    async fn response_headers(app: Router) -> HeaderMap {
+        let security = SecurityHeaders {
+            content_security_policy: HeaderValue::from_static(DEFAULT_CSP),
+        };
+        let app = app.layer(axum::middleware::from_fn_with_state(security, apply));
+        let request = Request::get("/").body(Body::empty()).unwrap();
+        app.oneshot(request).await.unwrap().headers().clone()
+    }
+
+    #[tokio::test]
+    async fn headers_are_added() {
+        let headers = response_headers(Router::new().route("/", get(|| async { "" }))).await;
+        assert_eq!(headers[CONTENT_SECURITY_POLICY], DEFAULT_CSP);
+        assert_eq!(headers[X_CONTENT_TYPE_OPTIONS], "nosniff");
+        assert_eq!(headers[REFERRER_POLICY], "strict-origin-when-cross-origin");
+        assert_eq!(headers[X_FRAME_OPTIONS], "DENY");
+    }
+
+    #[tokio::test]
+    async fn headers_set_by_the_handler_are_kept() {
+        let app = Router::new().route(
+            "/",
+            get(|| async { ([(X_FRAME_OPTIONS, "SAMEORIGIN")], "") }),
+        );
+        let headers = response_headers(app).await;
+        assert_eq!(headers[X_FRAME_OPTIONS], "SAMEORIGIN");
+        assert_eq!(headers.get_all(X_FRAME_OPTIONS).iter().count(), 1);
+        assert_eq!(headers[X_CONTENT_TYPE_OPTIONS], "nosniff");
+    }
+}
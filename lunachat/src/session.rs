@@ -0,0 +1,136 @@
+use std::env;
+use std::time::Duration;
+
+use axum::extract::{Request, State};
+use axum::middleware::Next;
+use axum::response::Response;
+use axum_login::tower_sessions::cookie::SameSite;
+use axum_login::tower_sessions::{Session, SessionManagerLayer, SessionStore};
+use chrono::{DateTime, Utc};
+
+use crate::auth::AuthSession;
+use crate::prelude::*;
+
+/// Session key holding when the logged-in user last made a request.
+const LAST_REQUEST_KEY: &str = "lunachat.last_request_at";
+
+/// Builds the session layer with cookie attributes from the environment:
+///
+/// - `LUNACHAT_COOKIE_SECURE`: `true` (the default) or `false`. Browsers accept secure
+///   cookies from `http://localhost`, so only development over plain http on another
+///   host needs to turn it off.
+/// - `LUNACHAT_COOKIE_SAMESITE`: `strict`, `lax` (the default) or `none`.
+/// - `LUNACHAT_COOKIE_NAME`: defaults to `lunachat.sid`.
+/// - `LUNACHAT_COOKIE_DOMAIN`: unset scopes the cookie to the serving host.
+pub fn layer<Store: SessionStore>(store: Store) -> Result<SessionManagerLayer<Store>> {
+    let secure = match env::var("LUNACHAT_COOKIE_SECURE") {
+        Ok(secure) => secure.parse()?,
+        Err(_) => true,
+    };
+    let same_site = match env::var("LUNACHAT_COOKIE_SAMESITE")
+        .map(|same_site| same_site.to_ascii_lowercase())
+        .as_deref()
+    {
+        Err(_) | Ok("lax") => SameSite::Lax,
+        Ok("strict") => SameSite::Strict,
+        Ok("none") => SameSite::None,
+        Ok(same_site) => return Err(anyhow!("Unknown LUNACHAT_COOKIE_SAMESITE {same_site:?}")),
+    };
+    let name = env::var("LUNACHAT_COOKIE_NAME").unwrap_or_else(|_| "lunachat.sid".into());
+
+    let layer = SessionManagerLayer::new(store)
+        .with_secure(secure)
+        .with_same_site(same_site)
+        .with_name(name);
+    Ok(match env::var("LUNACHAT_COOKIE_DOMAIN") {
+        Ok(domain) => layer.with_domain(domain),
+        Err(_) => layer,
+    })
+}
+
+/// How long a logged-in session may go without a request before it's logged out, from
+/// `LUNACHAT_IDLE_TIMEOUT` in minutes. Unset or `0` never logs anyone out for being idle.
+///
+/// Unlike the cookie's lifetime this slides: every request starts the window again.
+#[derive(Clone, Copy)]
+pub struct IdleTimeout(pub Option<Duration>);
+
+impl IdleTimeout {
+    pub fn from_env() -> Result<Self> {
+        Ok(Self(match env::var("LUNACHAT_IDLE_TIMEOUT") {
+            Ok(mins) => Some(Duration::from_secs(mins.parse::<u64>()? * 60))
+                .filter(|timeout| !timeout.is_zero()),
+            Err(_) => None,
+        }))
+    }
+
+    /// Whether a session last used at `last_request_at` has been idle too long by `now`.
+    /// Sessions that haven't recorded a request yet, or whose last one is somehow in the
+    /// future, haven't.
+    fn has_expired(&self, last_request_at: Option<DateTime<Utc>>, now: DateTime<Utc>) -> bool {
+        let Some(timeout) = self.0 else {
+            return false;
+        };
+        last_request_at
+            .and_then(|last| (now - last).to_std().ok())
+            .is_some_and(|idle| idle > timeout)
+    }
+}
+
+/// Logs out sessions that have been idle past the [`IdleTimeout`] before the request is
+/// handled, so it's treated like any other anonymous one, and records the request
+/// otherwise.
+pub async fn expire_idle(
+    State(timeout): State<IdleTimeout>,
+    mut auth: AuthSession,
+    session: Session,
+    mut req: Request,
+    next: Next,
+) -> Result<Response> {
+    if timeout.0.is_none() || auth.user.is_none() {
+        return Ok(next.run(req).await);
+    }
+
+    let now = Utc::now();
+    let last_request_at = session.get::<DateTime<Utc>>(LAST_REQUEST_KEY).await?;
+    if timeout.has_expired(last_request_at, now) {
+        tracing::debug!("Logging out idle session");
+        auth.logout().await.map_err(Box::new)?;
+        // Handlers extract their own copy of the auth session, which still has the user.
+        req.extensions_mut().insert(auth);
+    } else {
+        session.insert(LAST_REQUEST_KEY, now).await?;
+    }
+    Ok(next.run(req).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn sessions_expire_past_the_idle_window() {
+        let timeout = IdleTimeout(Some(30 * MINUTE));
+        let now = Utc::now();
+        let last = now - chrono::Duration::minutes(31);
+        assert!(timeout.has_expired(Some(last), now));
+    }
+
+    #[test]
+    fn recent_sessions_are_kept() {
+        let timeout = IdleTimeout(Some(30 * MINUTE));
+        let now = Utc::now();
+        assert!(!timeout.has_expired(Some(now - chrono::Duration::minutes(29)), now));
+        assert!(!timeout.has_expired(Some(now + chrono::Duration::minutes(5)), now));
+        assert!(!timeout.has_expired(None, now));
+    }
+
+    #[test]
+    fn no_timeout_never_expires() {
+        let now = Utc::now();
+        let last = now - chrono::Duration::days(365);
+        assert!(!IdleTimeout(None).has_expired(Some(last), now));
+    }
+}
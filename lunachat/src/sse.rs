@@ -0,0 +1,262 @@
+use std::collections::{HashMap, VecDeque};
+use std::convert::Infallible;
+use std::env;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use axum::extract::{FromRequestParts, Request};
+use axum::http::StatusCode;
+use axum::http::request::Parts;
+use axum::middleware::Next;
+use axum::response::sse::{Event, KeepAlive};
+use axum::response::{IntoResponse, Response, Sse};
+use axum::{BoxError, Extension, RequestPartsExt as _};
+use futures::{Stream, stream};
+use tokio::sync::oneshot;
+
+use crate::auth::AuthSession;
+use crate::prelude::*;
+use crate::proxy::ClientIp;
+
+#[derive(Clone, Copy)]
+pub struct SseConfig {
+    /// How often to send an empty comment on an idle stream. `None` sends nothing, for
+    /// deployments whose proxies don't time out idle connections.
+    pub keep_alive: Option<Duration>,
+    /// How long to hold back updates that follow closely on the previous one, so they can
+    /// go out together in one event. `None` sends every update as soon as it happens.
+    pub batch: Option<Duration>,
+}
+
+impl SseConfig {
+    /// Reads the keep-alive interval in seconds from `LUNACHAT_SSE_KEEP_ALIVE`, where `0`
+    /// disables it. Defaults to 15 seconds. The batching interval is read in milliseconds
+    /// from `LUNACHAT_SSE_BATCH_MS` and is off by default.
+    pub fn from_env() -> Result<Self> {
+        let keep_alive = match env::var("LUNACHAT_SSE_KEEP_ALIVE") {
+            Ok(secs) => Some(Duration::from_secs(secs.parse()?)).filter(|d| !d.is_zero()),
+            Err(_) => Some(Duration::from_secs(15)),
+        };
+        let batch = match env::var("LUNACHAT_SSE_BATCH_MS") {
+            Ok(millis) => Some(Duration::from_millis(millis.parse()?)).filter(|d| !d.is_zero()),
+            Err(_) => None,
+        };
+        Ok(Self { keep_alive, batch })
+    }
+
+    pub fn respond<S, E>(&self, stream: S) -> Response
+    where
+        S: Stream<Item = Result<Event, E>> + Send + 'static,
+        E: Into<BoxError>,
+    {
+        match self.keep_alive {
+            Some(interval) => Sse::new(stream)
+                .keep_alive(KeepAlive::new().interval(interval))
+                .into_response(),
+            None => Sse::new(stream).into_response(),
+        }
+    }
+}
+
+/// Ends the stream for anonymous clients with a single `auth-expired` event whose data is
+/// the login URL. A redirect would just look like a failed connection to `EventSource`,
+/// which keeps retrying, so this is how the page learns the session is gone.
+pub async fn require_login(auth: AuthSession, req: Request, next: Next) -> Response {
+    if auth.user.is_some() {
+        return next.run(req).await;
+    }
+    let event = Event::default()
+        .event("auth-expired")
+        .data(crate::url("/login"));
+    Sse::new(stream::once(async { Ok::<_, Infallible>(event) })).into_response()
+}
+
+/// Who an SSE stream is counted against: the logged-in user, or the [`ClientIp`] for
+/// anonymous visitors.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SseClient {
+    User(user::Id),
+    Ip(IpAddr),
+}
+
+impl<S> FromRequestParts<S> for SseClient
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self> {
+        let auth = parts
+            .extract::<AuthSession>()
+            .await
+            .map_err(|_| anyhow!("Auth not found"))?;
+        if let Some(user) = auth.user {
+            return Ok(SseClient::User(user.id));
+        }
+        let ClientIp(ip) = parts.extract::<ClientIp>().await?;
+        Ok(SseClient::Ip(ip))
+    }
+}
+
+/// What to do when a client opens more streams than it's allowed.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SseLimitPolicy {
+    /// Refuse the new stream with 429.
+    RejectNewest,
+    /// Close the client's oldest stream to make room.
+    CloseOldest,
+}
+
+#[derive(Default)]
+struct OpenStreams {
+    next_id: u64,
+    by_client: HashMap<SseClient, VecDeque<(u64, oneshot::Sender<()>)>>,
+}
+
+/// Caps how many SSE streams each [`SseClient`] may hold open at once.
+#[derive(Clone)]
+pub struct SseLimiter {
+    max: Option<usize>,
+    policy: SseLimitPolicy,
+    open: Arc<Mutex<OpenStreams>>,
+}
+
+impl SseLimiter {
+    /// Reads the cap from `LUNACHAT_SSE_PER_CLIENT` (default 5, `0` for no cap) and the
+    /// policy from `LUNACHAT_SSE_LIMIT_POLICY` (`reject`, the default, or `close-oldest`).
+    pub fn from_env() -> Result<Self> {
+        let max = match env::var("LUNACHAT_SSE_PER_CLIENT") {
+            Ok(max) => Some(max.parse()?).filter(|&max| max > 0),
+            Err(_) => Some(5),
+        };
+        let policy = match env::var("LUNACHAT_SSE_LIMIT_POLICY").as_deref() {
+            Err(_) | Ok("reject") => SseLimitPolicy::RejectNewest,
+            Ok("close-oldest") => SseLimitPolicy::CloseOldest,
+            Ok(policy) => return Err(anyhow!("Unknown LUNACHAT_SSE_LIMIT_POLICY {policy:?}")),
+        };
+        Ok(Self {
+            max,
+            policy,
+            open: Default::default(),
+        })
+    }
+
+    /// Claims a stream for `client`, or `None` if it's at the cap and the policy is to
+    /// reject. The returned receiver fires if the stream is later closed to make room.
+    pub fn acquire(&self, client: SseClient) -> Option<(SsePermit, oneshot::Receiver<()>)> {
+        let mut open = self.open.lock().unwrap_or_else(PoisonError::into_inner);
+        let id = open.next_id;
+        open.next_id += 1;
+
+        let streams = open.by_client.entry(client).or_default();
+        if let Some(max) = self.max
+            && streams.len() >= max
+        {
+            match self.policy {
+                SseLimitPolicy::RejectNewest => return None,
+                SseLimitPolicy::CloseOldest => {
+                    if let Some((_, close)) = streams.pop_front() {
+                        let _ = close.send(());
+                    }
+                }
+            }
+        }
+
+        let (close, closed) = oneshot::channel();
+        streams.push_back((id, close));
+        let permit = SsePermit {
+            open: self.open.clone(),
+            client,
+            id,
+        };
+        Some((permit, closed))
+    }
+}
+
+/// Holds a client's stream slot until dropped with the stream.
+pub struct SsePermit {
+    open: Arc<Mutex<OpenStreams>>,
+    client: SseClient,
+    id: u64,
+}
+
+impl Drop for SsePermit {
+    fn drop(&mut self) {
+        let mut open = self.open.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(streams) = open.by_client.get_mut(&self.client) {
+            streams.retain(|(id, _)| *id != self.id);
+            if streams.is_empty() {
+                open.by_client.remove(&self.client);
+            }
+        }
+    }
+}
+
+/// A stream slot claimed while extracting an SSE request, or `None` if the client was
+/// over its limit.
+pub struct SseSlot(pub Option<(SsePermit, oneshot::Receiver<()>)>);
+
+impl SseSlot {
+    pub fn rejected() -> Response {
+        (
+            StatusCode::TOO_MANY_REQUESTS,
+            "Too many live update streams open",
+        )
+            .into_response()
+    }
+}
+
+impl<S> FromRequestParts<S> for SseSlot
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self> {
+        let Extension(limiter) = parts.extract::<Extension<SseLimiter>>().await?;
+        let client = parts.extract::<SseClient>().await?;
+
+        Ok(SseSlot(limiter.acquire(client)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{HeaderMap, HeaderValue};
+
+    use super::*;
+    use crate::proxy::TrustedProxies;
+
+    fn limiter(max: usize) -> SseLimiter {
+        SseLimiter {
+            max: Some(max),
+            policy: SseLimitPolicy::RejectNewest,
+            open: Default::default(),
+        }
+    }
+
+    #[test]
+    fn forwarded_clients_behind_one_proxy_are_counted_apart() {
+        let proxies = TrustedProxies::new(["172.16.0.0/12"]).unwrap();
+        let peer = "172.18.0.5".parse().unwrap();
+        let client = |forwarded: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("x-forwarded-for", HeaderValue::from_str(forwarded).unwrap());
+            SseClient::Ip(proxies.client_ip(peer, &headers))
+        };
+
+        let limiter = limiter(1);
+        let _first = limiter.acquire(client("198.51.100.1")).unwrap();
+        let _second = limiter.acquire(client("198.51.100.2")).unwrap();
+        assert!(limiter.acquire(client("198.51.100.1")).is_none());
+    }
+
+    #[test]
+    fn dropped_permits_free_their_slot() {
+        let limiter = limiter(1);
+        let client = SseClient::User(Default::default());
+        drop(limiter.acquire(client).unwrap());
+        assert!(limiter.acquire(client).is_some());
+    }
+}
@@ -0,0 +1,125 @@
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum::{Extension, RequestPartsExt as _};
+use chrono::{DateTime, Days, Utc};
+
+use crate::mentions::link_mentions;
+use crate::pipeline::BodyPipeline;
+use crate::prelude::*;
+use crate::sanitizer::Sanitizer;
+use crate::unicode::TextNormalizer;
+use crate::validation::FieldErrors;
+use crate::word_filter::{FilteredContent, WordFilter};
+
+/// A rejected thread or reply, with what was typed so the form can be filled back in.
+#[derive(Clone)]
+pub struct SubmissionError {
+    /// About the submission as a whole rather than any one field. May be empty.
+    pub error: String,
+    pub field_errors: FieldErrors,
+    /// The thread being replied to, or `None` for a new thread.
+    pub thread_id: Option<thread::Id>,
+    pub title: String,
+    pub tags: String,
+    pub body: String,
+}
+
+impl SubmissionError {
+    /// The fields on the submitted form that errors are shown next to.
+    pub fn fields(&self) -> &'static [&'static str] {
+        match self.thread_id {
+            Some(_) => &["body", "publish_at"],
+            None => &["title", "body", "publish_at"],
+        }
+    }
+}
+
+/// Furthest ahead a post may be scheduled.
+const MAX_SCHEDULE_DAYS: u64 = 365;
+
+/// Parses a submitted `publish_at` in seconds since the Unix epoch. Blank or past times
+/// mean publishing right away, so they come back as `None`.
+pub fn parse_publish_at(publish_at: Option<&str>) -> Result<Option<DateTime<Utc>>, String> {
+    let Some(publish_at) = publish_at.map(str::trim).filter(|at| !at.is_empty()) else {
+        return Ok(None);
+    };
+    let publish_at = publish_at
+        .parse()
+        .ok()
+        .and_then(|secs| DateTime::from_timestamp(secs, 0))
+        .ok_or("That isn't a valid publishing time")?;
+    let now = Utc::now();
+    if publish_at <= now {
+        return Ok(None);
+    }
+    if now
+        .checked_add_days(Days::new(MAX_SCHEDULE_DAYS))
+        .is_some_and(|horizon| publish_at > horizon)
+    {
+        return Err(format!(
+            "Posts can be scheduled at most {MAX_SCHEDULE_DAYS} days ahead"
+        ));
+    }
+    Ok(Some(publish_at))
+}
+
+/// Turns submitted titles and bodies into the HTML that gets stored. Posting and
+/// previewing both go through this, so a preview can't differ from the stored post.
+#[derive(Clone)]
+pub struct SubmissionRenderer {
+    db: DatabaseConnection,
+    sanitizer: Sanitizer,
+    normalizer: TextNormalizer,
+    pipeline: BodyPipeline,
+    word_filter: WordFilter,
+}
+
+impl SubmissionRenderer {
+    pub fn title(&self, title: &str) -> Result<String, FilteredContent> {
+        let title = self
+            .normalizer
+            .apply(&self.sanitizer.clean(title).to_string());
+        self.word_filter.apply(&title)
+    }
+
+    /// Signatures are short and repeated under every post, so unlike bodies they don't
+    /// get images or mentions.
+    pub fn signature(&self, signature: &str) -> Result<String, FilteredContent> {
+        let signature = self
+            .normalizer
+            .apply(&self.sanitizer.clean(signature).to_string());
+        self.word_filter.apply(&signature)
+    }
+
+    pub async fn body(&self, body: &str) -> Result<Result<String, FilteredContent>> {
+        let body = self.pipeline.apply(body)?;
+        let body = match self.word_filter.apply(&body) {
+            Ok(body) => body,
+            Err(err) => return Ok(Err(err)),
+        };
+        Ok(Ok(link_mentions(&self.db, body).await?))
+    }
+}
+
+impl<S> FromRequestParts<S> for SubmissionRenderer
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self> {
+        let Extension(db) = parts.extract::<Extension<DatabaseConnection>>().await?;
+        let Extension(sanitizer) = parts.extract::<Extension<Sanitizer>>().await?;
+        let Extension(normalizer) = parts.extract::<Extension<TextNormalizer>>().await?;
+        let Extension(pipeline) = parts.extract::<Extension<BodyPipeline>>().await?;
+        let Extension(word_filter) = parts.extract::<Extension<WordFilter>>().await?;
+
+        Ok(SubmissionRenderer {
+            db,
+            sanitizer,
+            normalizer,
+            pipeline,
+            word_filter,
+        })
+    }
+}
@@ -0,0 +1,77 @@
+use axum::extract::{FromRequest, Path, Request};
+use axum::{Extension, RequestExt as _};
+
+use crate::maintenance::Maintenance;
+use crate::prelude::*;
+
+/// Turns maintenance mode on or off, holding the new state.
+pub struct MaintenancePost(pub bool);
+
+impl<S> FromRequest<S> for MaintenancePost
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request(mut req: Request, _state: &S) -> Result<Self> {
+        let Extension(maintenance) = req.extract_parts::<Extension<Maintenance>>().await?;
+        Ok(MaintenancePost(maintenance.toggle()))
+    }
+}
+
+pub struct ThreadPinPost(pub thread::Model);
+
+impl<S> FromRequest<S> for ThreadPinPost
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request(mut req: Request, _state: &S) -> Result<Self> {
+        let Extension(db) = req.extract_parts::<Extension<DatabaseConnection>>().await?;
+        let Path(thread::Key { id: thread_id, .. }) =
+            req.extract_parts::<Path<thread::Key>>().await?;
+
+        let thread = db.toggle_thread_pinned(thread_id).await?;
+
+        Ok(ThreadPinPost(thread))
+    }
+}
+
+pub struct PostApprovePost(pub post::Id, pub thread::Id);
+
+impl<S> FromRequest<S> for PostApprovePost
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request(mut req: Request, _state: &S) -> Result<Self> {
+        let Extension(db) = req.extract_parts::<Extension<DatabaseConnection>>().await?;
+        let Path(post_id) = req.extract_parts::<Path<post::Id>>().await?;
+
+        let post = db.approve_post(post_id).await?;
+
+        Ok(PostApprovePost(post.id, post.thread_id))
+    }
+}
+
+/// Deletes any account without a password, for moderators handling deletion requests.
+pub struct UserDeleteAdminPost(pub user::Id);
+
+impl<S> FromRequest<S> for UserDeleteAdminPost
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request(mut req: Request, _state: &S) -> Result<Self> {
+        let Extension(db) = req.extract_parts::<Extension<DatabaseConnection>>().await?;
+        let Extension(mode) = req.extract_parts::<Extension<user::DeleteMode>>().await?;
+        let Path(user_id) = req.extract_parts::<Path<user::Id>>().await?;
+
+        db.delete_user(user_id, mode).await?;
+
+        Ok(UserDeleteAdminPost(user_id))
+    }
+}
@@ -0,0 +1,72 @@
+use axum::extract::{FromRequest, FromRequestParts, Path, Request};
+use axum::http::request::Parts;
+use axum::{Extension, Form, RequestExt as _, RequestPartsExt as _};
+use serde::Deserialize;
+
+use crate::auth::AuthSession;
+use crate::prelude::*;
+
+/// The thread a draft route is for: `/thread/{thread_key}/draft` for a reply, or
+/// `/draft` for a new thread.
+fn draft_thread(path: Option<Path<thread::Key>>) -> thread::Id {
+    match path {
+        Some(Path(thread::Key { id, .. })) => id,
+        None => draft::new_thread(),
+    }
+}
+
+/// The logged-in user's saved draft for a thread, if any.
+pub struct DraftGet(pub Option<String>);
+
+impl<S> FromRequestParts<S> for DraftGet
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self> {
+        let auth = parts
+            .extract::<AuthSession>()
+            .await
+            .map_err(|_| anyhow!("Auth not found"))?;
+        let Extension(db) = parts.extract::<Extension<DatabaseConnection>>().await?;
+        let thread_id = draft_thread(parts.extract::<Option<Path<thread::Key>>>().await?);
+
+        let user = auth.user.ok_or(anyhow!("Not logged in"))?;
+        let draft = db.get_draft(user.id, thread_id).await?;
+        Ok(DraftGet(draft.map(|draft| draft.body)))
+    }
+}
+
+#[derive(Deserialize)]
+pub struct DraftSubmission {
+    pub body: String,
+}
+
+/// Saves the logged-in user's draft for a thread. An empty body discards it.
+pub struct DraftPut;
+
+impl<S> FromRequest<S> for DraftPut
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request(mut req: Request, _state: &S) -> Result<Self> {
+        let auth = req
+            .extract_parts::<AuthSession>()
+            .await
+            .map_err(|_| anyhow!("Auth not found"))?;
+        let Extension(db) = req.extract_parts::<Extension<DatabaseConnection>>().await?;
+        let thread_id = draft_thread(req.extract_parts::<Option<Path<thread::Key>>>().await?);
+        let Form(draft) = req.extract::<Form<DraftSubmission>, _>().await?;
+
+        let user = auth.user.ok_or(anyhow!("Not logged in"))?;
+        if draft.body.trim().is_empty() {
+            db.delete_draft(user.id, thread_id).await?;
+        } else {
+            db.save_draft(user.id, thread_id, draft.body).await?;
+        }
+        Ok(DraftPut)
+    }
+}
@@ -0,0 +1,71 @@
+use axum::extract::{FromRequestParts, Path};
+use axum::http::request::Parts;
+use axum::{Extension, RequestPartsExt as _};
+
+use super::partial;
+use crate::prelude::*;
+
+/// Number of newest threads in the site-wide feed.
+const FORUM_FEED_LEN: u64 = 20;
+
+/// Approved posts of a thread, for its Atom feed. Feeds are read anonymously, so only
+/// what an anonymous visitor could see is included.
+pub enum ThreadFeedGet {
+    Success {
+        thread: thread::Model,
+        posts: Vec<partial::PartialPostGet>,
+    },
+    /// The thread's opening post isn't public yet.
+    NotFound,
+}
+
+impl<S> FromRequestParts<S> for ThreadFeedGet
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self> {
+        let Extension(db) = parts.extract::<Extension<DatabaseConnection>>().await?;
+        let Path(thread::Key { id: thread_id, .. }) = parts.extract::<Path<thread::Key>>().await?;
+
+        if !db.get_root_post_of(thread_id).await?.visible_to(None) {
+            return Ok(ThreadFeedGet::NotFound);
+        }
+        let (thread, posts, authors) = db.get_thread_and_posts(thread_id).await?;
+        let posts = posts
+            .into_iter()
+            .filter(|post| post.visible_to(None))
+            .map(|post| partial::PartialPostGet {
+                author: authors
+                    .get(&post.author_id)
+                    .cloned()
+                    .unwrap_or_else(|| user::Model::deleted(post.author_id)),
+                post,
+            })
+            .collect();
+
+        Ok(ThreadFeedGet::Success { thread, posts })
+    }
+}
+
+/// The newest threads with public root posts, for the site-wide Atom feed.
+pub struct ForumFeedGet {
+    pub threads: Vec<partial::PartialThreadGet>,
+}
+
+impl<S> FromRequestParts<S> for ForumFeedGet
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self> {
+        let Extension(db) = parts.extract::<Extension<DatabaseConnection>>().await?;
+
+        let threads = db.get_newest_threads(None, FORUM_FEED_LEN).await?;
+        let threads = partial::PartialThreadGet::load_all(&db, threads).await?;
+
+        Ok(ForumFeedGet { threads })
+    }
+}
@@ -1,13 +1,19 @@
-use axum::extract::FromRequestParts;
+use axum::extract::{FromRequestParts, Path, Query};
 use axum::http::request::Parts;
 use axum::{Extension, RequestPartsExt as _};
 use sea_orm::DatabaseConnection;
 
 use super::partial;
+use crate::auth::AuthSession;
+use crate::pagination::{Page, PageQuery, PageSize};
 use crate::prelude::*;
 
+/// One page of the forum index, pinned threads first. Query strings that aren't a valid
+/// page show the first page.
 pub struct ForumGet {
     pub threads: Vec<partial::PartialThreadGet>,
+    pub page: Page,
+    pub total: u64,
 }
 
 impl<S> FromRequestParts<S> for ForumGet
@@ -17,24 +23,106 @@ where
     type Rejection = Error;
 
     async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self> {
+        let auth = parts
+            .extract::<AuthSession>()
+            .await
+            .map_err(|_| anyhow!("Auth not found"))?;
+        let Extension(db) = parts.extract::<Extension<DatabaseConnection>>().await?;
+        let Extension(size) = parts.extract::<Extension<PageSize>>().await?;
+        let Query(query) = parts.extract::<Query<PageQuery>>().await?;
+        let page = Page::from_query(query, size).unwrap_or(Page {
+            page: 0,
+            per_page: size.default,
+        });
+
+        let viewer = auth.user.as_ref();
+        let total = db.count_threads(viewer).await?;
+        let threads = db
+            .get_threads_page(viewer, page.offset(), page.per_page)
+            .await?;
+        let threads = partial::PartialThreadGet::load_all(&db, threads).await?;
+        Ok(ForumGet {
+            threads,
+            page,
+            total,
+        })
+    }
+}
+
+/// Threads carrying a single tag, in the same order as the forum index.
+pub struct TagGet {
+    pub tag: String,
+    pub threads: Vec<partial::PartialThreadGet>,
+}
+
+impl<S> FromRequestParts<S> for TagGet
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self> {
+        let auth = parts
+            .extract::<AuthSession>()
+            .await
+            .map_err(|_| anyhow!("Auth not found"))?;
         let Extension(db) = parts.extract::<Extension<DatabaseConnection>>().await?;
+        let Path(tag) = parts.extract::<Path<String>>().await?;
+        let tag = thread_tag::normalize(&tag);
 
-        let threads = thread::Entity::find()
-            .all(&db)
+        let threads = db.get_threads_tagged(&tag).await?;
+        let threads = partial::PartialThreadGet::load_all(&db, threads)
             .await?
             .into_iter()
-            .map_async(async |thread| {
-                let post = db.get_root_post_of(thread.id).await?;
-                let author = db.get_user(post.author_id).await?;
-                Ok(partial::PartialThreadGet {
-                    thread,
-                    post,
-                    author,
-                })
-            })
+            .filter(|template| template.post.visible_to(auth.user.as_ref()))
+            .collect();
+        Ok(TagGet { tag, threads })
+    }
+}
+
+/// One page of the forum index for `/api/threads`, along with how many threads the viewer
+/// can see in total for the `Link` and `X-Total-Count` headers.
+pub enum ThreadsPageGet {
+    Success {
+        threads: Vec<partial::PartialThreadGet>,
+        page: Page,
+        total: u64,
+    },
+    Failure {
+        error: String,
+    },
+}
+
+impl<S> FromRequestParts<S> for ThreadsPageGet
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self> {
+        let auth = parts
+            .extract::<AuthSession>()
             .await
-            .into_iter()
-            .collect::<Result<Vec<partial::PartialThreadGet>>>()?;
-        Ok(ForumGet { threads })
+            .map_err(|_| anyhow!("Auth not found"))?;
+        let Extension(db) = parts.extract::<Extension<DatabaseConnection>>().await?;
+        let Extension(size) = parts.extract::<Extension<PageSize>>().await?;
+        let Query(query) = parts.extract::<Query<PageQuery>>().await?;
+
+        let page = match Page::from_query(query, size) {
+            Ok(page) => page,
+            Err(error) => return Ok(ThreadsPageGet::Failure { error }),
+        };
+
+        let viewer = auth.user.as_ref();
+        let total = db.count_threads(viewer).await?;
+        let threads = db
+            .get_threads_page(viewer, page.offset(), page.per_page)
+            .await?;
+        let threads = partial::PartialThreadGet::load_all(&db, threads).await?;
+        Ok(ThreadsPageGet::Success {
+            threads,
+            page,
+            total,
+        })
     }
 }
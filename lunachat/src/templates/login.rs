@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use argon2::password_hash::rand_core::OsRng;
 use argon2::password_hash::{Salt, SaltString};
 use argon2::{Argon2, PasswordHasher};
@@ -5,8 +7,11 @@ use axum::extract::{FromRequest, FromRequestParts, Query, Request};
 use axum::http::request::Parts;
 use axum::{Extension, Form, RequestExt as _, RequestPartsExt as _};
 
-use crate::auth::{AuthSession, Credentials, NextUrl};
+use crate::auth::{AuthError, AuthSession, Credentials, NextUrl, sanitize_next};
 use crate::prelude::*;
+use crate::usernames::ReservedUsernames;
+use crate::validation::{FieldErrors, Validate as _};
+use crate::word_filter::WordFilter;
 
 pub struct LoginGet {
     pub error: Option<String>,
@@ -29,12 +34,17 @@ where
 pub enum LoginPost {
     Success {
         user: user::Model,
-        next: Option<String>,
+        next: String,
     },
     Failure {
         error: String,
         next: Option<String>,
     },
+    /// The username has failed too often recently and may try again after `retry_after`.
+    TooManyAttempts {
+        retry_after: Duration,
+        next: Option<String>,
+    },
 }
 
 impl<S> FromRequest<S> for LoginPost
@@ -50,21 +60,28 @@ where
             .map_err(|_| anyhow!("Auth not found"))?;
         let Form(creds) = req.extract::<Form<Credentials>, _>().await?;
 
-        let user = match auth.authenticate(creds.clone()).await.map_err(Box::new)? {
-            Some(user) => user,
-            None => {
+        let user = match auth.authenticate(creds.clone()).await {
+            Ok(Some(user)) => user,
+            Ok(None) => {
                 return Ok(LoginPost::Failure {
                     error: "Username or password incorrect".into(),
                     next: creds.next,
                 });
             }
+            Err(axum_login::Error::Backend(AuthError::TooManyAttempts(retry_after))) => {
+                return Ok(LoginPost::TooManyAttempts {
+                    retry_after,
+                    next: creds.next,
+                });
+            }
+            Err(err) => return Err(Box::new(err).into()),
         };
 
         auth.login(&user).await.map_err(Box::new)?;
 
         Ok(LoginPost::Success {
             user,
-            next: creds.next,
+            next: sanitize_next(creds.next),
         })
     }
 }
@@ -72,10 +89,10 @@ where
 pub enum RegisterPost {
     Success {
         user: user::Model,
-        next: Option<String>,
+        next: String,
     },
     Failure {
-        error: String,
+        errors: FieldErrors,
         next: Option<String>,
     },
 }
@@ -92,13 +109,23 @@ where
             .await
             .map_err(|_| anyhow!("Auth not found"))?;
         let Extension(db) = req.extract_parts::<Extension<DatabaseConnection>>().await?;
+        let Extension(reserved) = req.extract_parts::<Extension<ReservedUsernames>>().await?;
+        let Extension(word_filter) = req.extract_parts::<Extension<WordFilter>>().await?;
         let Form(creds) = req.extract::<Form<Credentials>, _>().await?;
 
-        if db.find_user_by_username(&creds.username).await?.is_some() {
-            return Ok(RegisterPost::Failure {
-                error: "Username already taken".into(),
-                next: None,
-            });
+        let mut errors = creds.validate();
+        // Anything the word filter would touch doesn't make a good name either.
+        let filtered = word_filter.apply(&creds.username).ok();
+        if user::is_reserved(&creds.username)
+            || reserved.contains(&creds.username)
+            || filtered.as_deref() != Some(creds.username.as_str())
+        {
+            errors.add("username", "Username not allowed");
+        } else if db.find_user_by_username(&creds.username).await?.is_some() {
+            errors.add("username", "Username already taken");
+        }
+        if !errors.is_empty() {
+            return Ok(RegisterPost::Failure { errors, next: None });
         }
 
         let salt_string = SaltString::generate(&mut OsRng);
@@ -118,7 +145,7 @@ where
 
         Ok(RegisterPost::Success {
             user,
-            next: creds.next,
+            next: sanitize_next(creds.next),
         })
     }
 }
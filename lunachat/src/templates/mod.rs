@@ -1,8 +1,17 @@
-pub use forum::ForumGet;
+pub use admin::{MaintenancePost, PostApprovePost, ThreadPinPost, UserDeleteAdminPost};
+pub use draft::{DraftGet, DraftPut};
+pub use feed::{ForumFeedGet, ThreadFeedGet};
+pub use forum::{ForumGet, TagGet, ThreadsPageGet};
 pub use login::{LoginGet, LoginPost, LogoutPost, RegisterPost};
-pub use thread::{PostPost, ThreadGet, ThreadPost};
-pub use user::UserGet;
+pub use thread::{
+    AcceptAnswerPost, PostGet, PostPost, PreviewPost, ShowSignatures, ThreadGet, ThreadPage,
+    ThreadPost, ThreadPosts,
+};
+pub use user::{SignaturePost, TimezonePost, UserDeletePost, UserGet, UserSearchGet};
 
+mod admin;
+mod draft;
+mod feed;
 mod forum;
 mod login;
 pub mod partial;
@@ -1,15 +1,20 @@
-use std::time::Duration;
+use std::collections::HashSet;
 
-use axum::extract::{FromRequestParts, Path};
+use axum::extract::{FromRequestParts, Path, Query};
+use axum::http::StatusCode;
 use axum::http::request::Parts;
 use axum::response::sse::Event;
-use axum::response::{IntoResponse, Sse};
+use axum::response::{IntoResponse as _, Response};
 use axum::{Extension, RequestPartsExt as _};
-use futures::stream;
+use futures::{StreamExt as _, stream};
 use serde::{Deserialize, Serialize};
 use tokio::sync::broadcast::Receiver;
+use tokio::time::{Duration, Instant, timeout_at};
 
+use crate::auth::AuthSession;
+use crate::metrics::SseConnection;
 use crate::prelude::*;
+use crate::sse::{SseConfig, SseSlot};
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct PartialPostGet {
@@ -17,56 +22,145 @@ pub struct PartialPostGet {
     pub author: user::Model,
 }
 
+#[derive(Deserialize)]
+pub struct SnapshotQuery {
+    pub snapshot: Option<String>,
+}
+
 pub struct PostSse {
     db: DatabaseConnection,
     thread_id: thread::Id,
+    config: SseConfig,
+    slot: SseSlot,
+    sub: Receiver<BroadcastEvent<post::Model>>,
+    /// The thread's public posts as of subscribing, sent before anything live when the
+    /// stream is opened with `?snapshot=1`.
+    snapshot: Vec<PartialPostGet>,
+    /// Whether the viewer can see the thread's opening post. Streams of threads they
+    /// can't are answered with 404.
+    visible: bool,
 }
 
 impl PostSse {
+    /// Streams each new post in the thread as rendered by `mapper`, after one event per
+    /// post in the snapshot if one was asked for.
+    ///
+    /// The subscription is taken before the snapshot is read, so a post created in between
+    /// is waiting in the channel rather than lost. Its creation is skipped if the snapshot
+    /// already had it.
+    ///
+    /// With [`SseConfig::batch`] set, a post that arrives within the interval of the last
+    /// event is held back until the interval is up, and everything that arrived by then
+    /// goes out as one event: the fragments joined by newlines, which arrive as a single
+    /// `data` payload to append as a whole. A post after a quiet spell is sent right away.
     pub fn into_sse(
         self,
         mapper: impl Fn(PartialPostGet) -> Result<String> + Send + Sync + 'static,
-    ) -> impl IntoResponse {
+    ) -> Response {
+        /// Waits for the next post to show, or `None` once `deadline` passes. Only the wait
+        /// is cut short, so a post that has arrived is never dropped half-rendered.
         async fn get_valid_single(
             sub: &mut Receiver<BroadcastEvent<post::Model>>,
             db: &DatabaseConnection,
             thread_id: thread::Id,
             mapper: impl Fn(PartialPostGet) -> Result<String>,
-        ) -> Result<Event> {
+            sent: &HashSet<post::Id>,
+            deadline: Option<Instant>,
+        ) -> Result<Option<String>> {
             loop {
-                let post = match sub.recv().await? {
+                let event = match deadline {
+                    Some(deadline) => match timeout_at(deadline, sub.recv()).await {
+                        Ok(event) => event?,
+                        Err(_) => return Ok(None),
+                    },
+                    None => sub.recv().await?,
+                };
+                let post = match event {
+                    BroadcastEvent::Create(value) if sent.contains(&value.id) => continue,
                     BroadcastEvent::Create(value) => value,
                     BroadcastEvent::Update(value) => value,
                     BroadcastEvent::Delete => continue,
                 };
-                if post.thread_id != thread_id {
+                if post.thread_id != thread_id || !post.is_public() {
                     continue;
                 }
-                let author = db.get_user(post.author_id).await?;
+                let author = db.get_author(post.author_id).await?;
                 let template = PartialPostGet { post, author };
-                let data = mapper(template)?;
-                let event = Event::default().data(data);
-                return Ok(event);
+                return Ok(Some(mapper(template)?));
             }
         }
 
-        let Self { db, thread_id } = self;
-        let sub = post::BROADCAST.subscribe();
-        let stream = stream::unfold(
-            (sub, db, thread_id, mapper),
-            async move |(mut sub, db, thread_id, mapper)| {
+        async fn get_batch(
+            sub: &mut Receiver<BroadcastEvent<post::Model>>,
+            db: &DatabaseConnection,
+            thread_id: thread::Id,
+            mapper: impl Fn(PartialPostGet) -> Result<String>,
+            sent: &HashSet<post::Id>,
+            batch: Option<Duration>,
+            last_sent: &mut Option<Instant>,
+        ) -> Result<Event> {
+            let mut data = Vec::new();
+            data.extend(get_valid_single(sub, db, thread_id, &mapper, sent, None).await?);
+            if let (Some(batch), Some(last_sent)) = (batch, *last_sent)
+                && last_sent.elapsed() < batch
+            {
+                let deadline = Some(last_sent + batch);
+                while let Some(fragment) =
+                    get_valid_single(sub, db, thread_id, &mapper, sent, deadline).await?
+                {
+                    data.push(fragment);
+                }
+            }
+            *last_sent = Some(Instant::now());
+            Ok(Event::default().data(data.join("\n")))
+        }
+
+        let Self {
+            db,
+            thread_id,
+            config,
+            slot,
+            sub,
+            snapshot,
+            visible,
+        } = self;
+        if !visible {
+            return StatusCode::NOT_FOUND.into_response();
+        }
+        let Some((permit, closed)) = slot.0 else {
+            return SseSlot::rejected();
+        };
+        let conn = SseConnection::open(format!("thread {thread_id}"));
+        let batch = config.batch;
+        let sent = snapshot
+            .iter()
+            .map(|template| template.post.id)
+            .collect::<HashSet<_>>();
+        let snapshot = snapshot
+            .into_iter()
+            .map(|template| -> Result<Event> { Ok(Event::default().data(mapper(template)?)) })
+            .collect::<Vec<_>>();
+        let live = stream::unfold(
+            (sub, db, thread_id, mapper, sent, None, conn, permit),
+            async move |(mut sub, db, thread_id, mapper, sent, mut last_sent, conn, permit)| {
                 Some((
-                    get_valid_single(&mut sub, &db, thread_id, &mapper).await,
-                    (sub, db, thread_id, mapper),
+                    get_batch(
+                        &mut sub,
+                        &db,
+                        thread_id,
+                        &mapper,
+                        &sent,
+                        batch,
+                        &mut last_sent,
+                    )
+                    .await,
+                    (sub, db, thread_id, mapper, sent, last_sent, conn, permit),
                 ))
             },
         );
+        let stream = stream::iter(snapshot).chain(live).take_until(closed);
 
-        Sse::new(stream).keep_alive(
-            axum::response::sse::KeepAlive::new()
-                .interval(Duration::from_secs(1))
-                .text("keep-alive-text"),
-        )
+        config.respond(stream)
     }
 }
 
@@ -77,9 +171,53 @@ where
     type Rejection = Error;
 
     async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self> {
+        let auth = parts
+            .extract::<AuthSession>()
+            .await
+            .map_err(|_| anyhow!("Auth not found"))?;
         let Extension(db) = parts.extract::<Extension<DatabaseConnection>>().await?;
-        let Path(thread_id) = parts.extract::<Path<thread::Id>>().await?;
+        let Extension(config) = parts.extract::<Extension<SseConfig>>().await?;
+        let Path(thread::Key { id: thread_id, .. }) = parts.extract::<Path<thread::Key>>().await?;
+        let Query(SnapshotQuery { snapshot }) = parts.extract::<Query<SnapshotQuery>>().await?;
+
+        let visible = db
+            .get_root_post_of(thread_id)
+            .await?
+            .visible_to(auth.user.as_ref());
+        // A hidden thread's stream isn't counted against the client's limit.
+        let slot = if visible {
+            parts.extract::<SseSlot>().await?
+        } else {
+            SseSlot(None)
+        };
+
+        let sub = post::BROADCAST.subscribe();
+        // A rejected stream never gets as far as sending the snapshot.
+        let snapshot = if visible && slot.0.is_some() && snapshot.as_deref() == Some("1") {
+            let (_thread, posts, authors) = db.get_thread_and_posts(thread_id).await?;
+            posts
+                .into_iter()
+                .filter(|post| post.is_public())
+                .map(|post| PartialPostGet {
+                    author: authors
+                        .get(&post.author_id)
+                        .cloned()
+                        .unwrap_or_else(|| user::Model::deleted(post.author_id)),
+                    post,
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
 
-        Ok(PostSse { db, thread_id })
+        Ok(PostSse {
+            db,
+            thread_id,
+            config,
+            slot,
+            sub,
+            snapshot,
+            visible,
+        })
     }
 }
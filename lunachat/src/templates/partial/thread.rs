@@ -1,32 +1,102 @@
-use std::time::Duration;
-
 use axum::extract::FromRequestParts;
 use axum::http::request::Parts;
+use axum::response::Response;
 use axum::response::sse::Event;
-use axum::response::{IntoResponse, Sse};
 use axum::{Extension, RequestPartsExt as _};
-use futures::stream;
+use futures::{StreamExt as _, stream};
 use serde::{Deserialize, Serialize};
 use tokio::sync::broadcast::Receiver;
 
+use crate::metrics::SseConnection;
 use crate::prelude::*;
+use crate::sse::{SseConfig, SseSlot};
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct PartialThreadGet {
     pub thread: thread::Model,
     pub post: post::Model,
     pub author: user::Model,
+    pub tags: Vec<String>,
+    /// Approved posts in the thread, including the opening post.
+    pub num_posts: u64,
+    /// The newest approved reply and its author, if anyone has replied.
+    pub last_reply: Option<(post::Model, user::Model)>,
+}
+
+impl PartialThreadGet {
+    /// Looks up everything shown alongside `thread` in a thread listing.
+    pub async fn load(db: &DatabaseConnection, thread: thread::Model) -> Result<Self> {
+        let id = thread.id;
+        Self::load_all(db, vec![thread])
+            .await?
+            .pop()
+            .ok_or(anyhow!("Thread {id} has no root post"))
+    }
+
+    /// Like [`load`](Self::load) for a whole listing, fetching the root posts, last
+    /// replies, their authors, tags and post counts of every thread at once. `threads`
+    /// keep their order.
+    pub async fn load_all(
+        db: &DatabaseConnection,
+        threads: Vec<thread::Model>,
+    ) -> Result<Vec<Self>> {
+        if threads.is_empty() {
+            return Ok(Vec::new());
+        }
+        let ids = threads.iter().map(|thread| thread.id).collect::<Vec<_>>();
+        let mut roots = db.get_root_posts_of(&ids).await?;
+        let root_ids = roots.values().map(|post| post.id).collect::<Vec<_>>();
+        let mut last_replies = db.get_last_replies_of(&ids, &root_ids).await?;
+        let mut tags = db.get_thread_tags(&ids).await?;
+        let counts = db.count_approved_posts_in(&ids).await?;
+        let authors = db
+            .find_users(
+                roots
+                    .values()
+                    .chain(last_replies.values())
+                    .map(|post| post.author_id),
+            )
+            .await?;
+        let author = |id: user::Id| {
+            authors
+                .get(&id)
+                .cloned()
+                .unwrap_or_else(|| user::Model::deleted(id))
+        };
+
+        let mut templates = Vec::with_capacity(threads.len());
+        for thread in threads {
+            let Some(post) = roots.remove(&thread.id) else {
+                return Err(anyhow!("Thread {} has no root post", thread.id));
+            };
+            let last_reply = last_replies.remove(&thread.id).map(|reply| {
+                let author = author(reply.author_id);
+                (reply, author)
+            });
+            templates.push(Self {
+                tags: tags.remove(&thread.id).unwrap_or_default(),
+                num_posts: counts.get(&thread.id).copied().unwrap_or_default(),
+                author: author(post.author_id),
+                last_reply,
+                thread,
+                post,
+            });
+        }
+        Ok(templates)
+    }
 }
 
 pub struct ThreadSse {
     db: DatabaseConnection,
+    config: SseConfig,
+    slot: SseSlot,
 }
 
 impl ThreadSse {
     pub fn into_sse(
         self,
         mapper: impl Fn(PartialThreadGet) -> Result<String> + Send + Sync + 'static,
-    ) -> impl IntoResponse {
+    ) -> Response {
         async fn get_valid_single(
             sub: &mut Receiver<BroadcastEvent<thread::Model>>,
             db: &DatabaseConnection,
@@ -38,33 +108,34 @@ impl ThreadSse {
                     BroadcastEvent::Update(value) => value,
                     BroadcastEvent::Delete => continue,
                 };
-                let post = db.get_root_post_of(thread.id).await?;
-                let author = db.get_user(post.author_id).await?;
-                let template = PartialThreadGet {
-                    thread,
-                    post,
-                    author,
-                };
+                let template = PartialThreadGet::load(db, thread).await?;
+                if !template.post.is_public() {
+                    continue;
+                }
                 let data = mapper(template)?;
                 let event = Event::default().data(data);
                 return Ok(event);
             }
         }
 
-        let Self { db } = self;
+        let Self { db, config, slot } = self;
+        let Some((permit, closed)) = slot.0 else {
+            return SseSlot::rejected();
+        };
         let sub = thread::BROADCAST.subscribe();
-        let stream = stream::unfold((sub, db, mapper), async move |(mut sub, db, mapper)| {
-            Some((
-                get_valid_single(&mut sub, &db, &mapper).await,
-                (sub, db, mapper),
-            ))
-        });
-
-        Sse::new(stream).keep_alive(
-            axum::response::sse::KeepAlive::new()
-                .interval(Duration::from_secs(1))
-                .text("keep-alive-text"),
+        let conn = SseConnection::open("forum");
+        let stream = stream::unfold(
+            (sub, db, mapper, conn, permit),
+            async move |(mut sub, db, mapper, conn, permit)| {
+                Some((
+                    get_valid_single(&mut sub, &db, &mapper).await,
+                    (sub, db, mapper, conn, permit),
+                ))
+            },
         )
+        .take_until(closed);
+
+        config.respond(stream)
     }
 }
 
@@ -76,7 +147,9 @@ where
 
     async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self> {
         let Extension(db) = parts.extract::<Extension<DatabaseConnection>>().await?;
+        let Extension(config) = parts.extract::<Extension<SseConfig>>().await?;
+        let slot = parts.extract::<SseSlot>().await?;
 
-        Ok(ThreadSse { db })
+        Ok(ThreadSse { db, config, slot })
     }
 }
@@ -1,16 +1,66 @@
-use axum::extract::{FromRequest, FromRequestParts, Path, Request};
+use axum::extract::{FromRequest, FromRequestParts, Path, Query, Request};
 use axum::http::request::Parts;
 use axum::{Extension, Form, RequestExt as _, RequestPartsExt as _};
+use axum_login::tower_sessions::Session;
 use serde::{Deserialize, Serialize};
 
 use super::partial;
 use crate::auth::AuthSession;
+use crate::idempotency::{Claim, IdempotencyKeys};
+use crate::moderation::{ApprovalThreshold, MaxPostsPerThread, MinAccountAge};
+use crate::negotiate::Format;
 use crate::prelude::*;
-use crate::sanitizer::Sanitizer;
+use crate::render_cache::RenderCache;
+use crate::submission::{SubmissionError, SubmissionRenderer, parse_publish_at};
+use crate::timezone::ViewerTimezone;
+use crate::validation::{FieldErrors, Validate};
+use crate::views::ViewWindow;
 
-pub struct ThreadGet {
+pub enum ThreadGet {
+    Success(ThreadPage),
+    /// The viewer can't see the thread's opening post, because it's pending approval or
+    /// scheduled for later.
+    NotFound,
+}
+
+pub struct ThreadPage {
     pub thread: thread::Model,
-    pub posts: Vec<partial::PartialPostGet>,
+    pub posts: ThreadPosts,
+    /// The slug the thread was requested with, which may be stale or missing.
+    pub slug: Option<String>,
+}
+
+pub enum ThreadPosts {
+    /// The page's posts, already rendered, from the [`RenderCache`]. Only for HTML.
+    Cached(String),
+    Loaded {
+        posts: Vec<partial::PartialPostGet>,
+        /// When the viewer sees the thread as everyone else does, the
+        /// [version](RenderCache::version) to cache the rendered posts under.
+        version: Option<u64>,
+    },
+}
+
+/// Whether `viewer` sees the thread exactly as a guest would. Moderators and the thread's
+/// author get extra controls, and authors of pending or scheduled posts see those too.
+async fn sees_public_view(
+    db: &DatabaseConnection,
+    root: &post::Model,
+    viewer: Option<&user::Model>,
+) -> Result<bool> {
+    let Some(viewer) = viewer else {
+        return Ok(true);
+    };
+    if viewer.moderator || root.author_id == viewer.id {
+        return Ok(false);
+    }
+    Ok(db.count_hidden_posts_by(viewer.id, root.thread_id).await? == 0)
+}
+
+impl ThreadPage {
+    pub fn is_canonical(&self) -> bool {
+        self.slug.as_deref().unwrap_or_default() == self.thread.slug
+    }
 }
 
 impl<S> FromRequestParts<S> for ThreadGet
@@ -20,19 +70,159 @@ where
     type Rejection = Error;
 
     async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self> {
+        let auth = parts
+            .extract::<AuthSession>()
+            .await
+            .map_err(|_| anyhow!("Auth not found"))?;
+        let session = parts
+            .extract::<Session>()
+            .await
+            .map_err(|_| anyhow!("Session not found"))?;
         let Extension(db) = parts.extract::<Extension<DatabaseConnection>>().await?;
-        let Path(thread_id) = parts.extract::<Path<thread::Id>>().await?;
+        let Extension(view_window) = parts.extract::<Extension<ViewWindow>>().await?;
+        let Extension(render_cache) = parts.extract::<Extension<RenderCache>>().await?;
+        let format = parts.extract::<Format>().await?;
+        let ShowSignatures(show_signatures) = parts.extract::<ShowSignatures>().await?;
+        let timezone = parts.extract::<ViewerTimezone>().await?;
+        let Path(thread::Key {
+            id: thread_id,
+            slug,
+        }) = parts.extract::<Path<thread::Key>>().await?;
+
+        let root = db.get_root_post_of(thread_id).await?;
+        if !root.visible_to(auth.user.as_ref()) {
+            return Ok(ThreadGet::NotFound);
+        }
+        view_window.record(&session, &db, thread_id).await?;
+        let thread = db.get_thread(thread_id).await?;
+
+        // Checked before loading any posts, so a hit costs a couple of small queries.
+        let version = if render_cache.is_enabled()
+            && format == Format::Html
+            && sees_public_view(&db, &root, auth.user.as_ref()).await?
+        {
+            let version =
+                RenderCache::version(&thread, db.summarize_public_posts_in(thread_id).await?);
+            if let Some(html) = render_cache.get(&(thread_id, show_signatures, timezone), version) {
+                return Ok(ThreadGet::Success(ThreadPage {
+                    thread,
+                    posts: ThreadPosts::Cached(html),
+                    slug,
+                }));
+            }
+            Some(version)
+        } else {
+            None
+        };
 
-        let (thread, posts, authors) = db.get_thread_and_posts(thread_id).await?;
-        let posts = posts
+        let (posts, authors) = db.get_posts_with_authors_of(thread_id).await?;
+        let mut posts = posts
             .into_iter()
+            .filter(|post| post.visible_to(auth.user.as_ref()))
             .map(|post| partial::PartialPostGet {
-                author: authors[&post.author_id].clone(),
+                author: authors
+                    .get(&post.author_id)
+                    .cloned()
+                    .unwrap_or_else(|| user::Model::deleted(post.author_id)),
                 post,
             })
             .collect::<Vec<_>>();
+        // The accepted answer goes straight under the question.
+        if let Some(accepted) = thread.accepted_answer
+            && let Some(index) = posts.iter().position(|post| post.post.id == accepted)
+            && index > 1
+        {
+            let answer = posts.remove(index);
+            posts.insert(1, answer);
+        }
+
+        Ok(ThreadGet::Success(ThreadPage {
+            thread,
+            posts: ThreadPosts::Loaded { posts, version },
+            slug,
+        }))
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PostPath {
+    pub thread_key: thread::Key,
+    pub post_key: post::Id,
+}
+
+/// A single post on its own, for permalinks and fragments loaded into other pages.
+pub enum PostGet {
+    Success {
+        thread: thread::Model,
+        post: partial::PartialPostGet,
+    },
+    /// The post doesn't exist, isn't in the named thread, or the viewer can't see it or
+    /// the thread's opening post.
+    NotFound,
+}
+
+impl<S> FromRequestParts<S> for PostGet
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self> {
+        let auth = parts
+            .extract::<AuthSession>()
+            .await
+            .map_err(|_| anyhow!("Auth not found"))?;
+        let Extension(db) = parts.extract::<Extension<DatabaseConnection>>().await?;
+        let Path(PostPath {
+            thread_key: thread::Key { id: thread_id, .. },
+            post_key,
+        }) = parts.extract::<Path<PostPath>>().await?;
+
+        let Some(post) = db.find_post(post_key).await? else {
+            return Ok(PostGet::NotFound);
+        };
+        if post.thread_id != thread_id || !post.visible_to(auth.user.as_ref()) {
+            return Ok(PostGet::NotFound);
+        }
+        if !db
+            .get_root_post_of(thread_id)
+            .await?
+            .visible_to(auth.user.as_ref())
+        {
+            return Ok(PostGet::NotFound);
+        }
+
+        let thread = db.get_thread(thread_id).await?;
+        let author = db
+            .find_user(post.author_id)
+            .await?
+            .unwrap_or_else(|| user::Model::deleted(post.author_id));
+        Ok(PostGet::Success {
+            thread,
+            post: partial::PartialPostGet { post, author },
+        })
+    }
+}
 
-        Ok(ThreadGet { thread, posts })
+#[derive(Deserialize)]
+pub struct SignatureQuery {
+    pub sigs: Option<String>,
+}
+
+/// Whether to render signatures under posts. Viewers can hide them with `?sigs=off`.
+#[derive(Clone, Copy)]
+pub struct ShowSignatures(pub bool);
+
+impl<S> FromRequestParts<S> for ShowSignatures
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self> {
+        let Query(SignatureQuery { sigs }) = parts.extract::<Query<SignatureQuery>>().await?;
+
+        Ok(ShowSignatures(sigs.as_deref() != Some("off")))
     }
 }
 
@@ -40,9 +230,45 @@ where
 pub struct ThreadSubmission {
     pub title: String,
     pub body: String,
+    /// Comma-separated.
+    #[serde(default)]
+    pub tags: String,
+    /// Seconds since the Unix epoch to hold the thread back until.
+    #[serde(default)]
+    pub publish_at: Option<String>,
+    /// Client-chosen key that makes retrying the same submission safe.
+    #[serde(default)]
+    pub idempotency_key: Option<String>,
+}
+
+impl Validate for ThreadSubmission {
+    fn validate(&self) -> FieldErrors {
+        const MAX_TITLE_LEN: usize = 200;
+
+        let mut errors = FieldErrors::default();
+        if self.title.trim().is_empty() {
+            errors.add("title", "Threads need a title");
+        } else if self.title.chars().count() > MAX_TITLE_LEN {
+            errors.add(
+                "title",
+                format!("Titles can be at most {MAX_TITLE_LEN} characters"),
+            );
+        }
+        if self.body.trim().is_empty() {
+            errors.add("body", "Posts can't be empty");
+        }
+        errors
+    }
 }
 
-pub struct ThreadPost(pub thread::Id);
+pub enum ThreadPost {
+    Success(thread::Model),
+    Failure(SubmissionError),
+    /// The author's account is younger than [`MinAccountAge`].
+    TooNew(SubmissionError),
+    /// The same submission is already being handled.
+    InFlight,
+}
 
 impl<S> FromRequest<S> for ThreadPost
 where
@@ -56,30 +282,172 @@ where
             .await
             .map_err(|_| anyhow!("Auth not found"))?;
         let Extension(db) = req.extract_parts::<Extension<DatabaseConnection>>().await?;
-        let Extension(sanitizer) = req.extract_parts::<Extension<Sanitizer>>().await?;
+        let Extension(approval) = req.extract_parts::<Extension<ApprovalThreshold>>().await?;
+        let Extension(min_age) = req.extract_parts::<Extension<MinAccountAge>>().await?;
+        let Extension(idempotency) = req
+            .extract_parts::<Extension<IdempotencyKeys<thread::Id>>>()
+            .await?;
+        let renderer = req.extract_parts::<SubmissionRenderer>().await?;
         let Form(thread_form) = req.extract::<Form<ThreadSubmission>, _>().await?;
 
-        let title = sanitizer.clean(&thread_form.title).to_string();
-        let body = sanitizer.clean(&thread_form.body).to_string();
+        let author = auth.user.ok_or(anyhow!("Not logged in"))?;
+        if let Some(wait) = min_age.wait(&author) {
+            return Ok(ThreadPost::TooNew(SubmissionError {
+                error: MinAccountAge::message(wait),
+                field_errors: FieldErrors::default(),
+                thread_id: None,
+                title: thread_form.title,
+                tags: thread_form.tags,
+                body: thread_form.body,
+            }));
+        }
+
+        let mut errors = thread_form.validate();
+        let reservation = match thread_form.idempotency_key.filter(|key| !key.is_empty()) {
+            Some(key) => match idempotency.claim(author.id, key) {
+                Claim::New(reservation) => Some(reservation),
+                Claim::Done(thread_id) => {
+                    return Ok(ThreadPost::Success(db.get_thread(thread_id).await?));
+                }
+                Claim::InFlight => return Ok(ThreadPost::InFlight),
+            },
+            None => None,
+        };
+        let title = renderer
+            .title(&thread_form.title)
+            .inspect_err(|err| errors.add("title", err.to_string()));
+        let body = renderer
+            .body(&thread_form.body)
+            .await?
+            .inspect_err(|err| errors.add("body", err.to_string()));
+        let publish_at = parse_publish_at(thread_form.publish_at.as_deref())
+            .inspect_err(|err| errors.add("publish_at", err));
+        let (Ok(title), Ok(body), Ok(publish_at), true) =
+            (title, body, publish_at, errors.is_empty())
+        else {
+            return Ok(ThreadPost::Failure(SubmissionError {
+                error: String::new(),
+                field_errors: errors,
+                thread_id: None,
+                title: thread_form.title,
+                tags: thread_form.tags,
+                body: thread_form.body,
+            }));
+        };
+
+        let approved = approval.approves(&db, &author).await?;
 
         let (thread, _post) = db
             .insert_thread(thread::NewModel {
                 title,
+                slug: thread::slugify(&thread_form.title),
+                tags: thread_tag::parse(&thread_form.tags),
                 body,
-                author_id: auth.user.ok_or(anyhow!("Not logged in"))?.id,
+                author_id: author.id,
+                approved,
+                publish_at,
             })
             .await?;
+        if let Some(reservation) = reservation {
+            reservation.complete(thread.id);
+        }
+        db.delete_draft(author.id, draft::new_thread()).await?;
+
+        Ok(ThreadPost::Success(thread))
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AcceptAnswerPath {
+    pub thread_key: thread::Key,
+    /// Missing when clearing the accepted answer.
+    pub post_key: Option<post::Id>,
+}
+
+/// Marks a reply as the thread's accepted answer, or clears it. Only the thread's author
+/// and moderators may.
+pub enum AcceptAnswerPost {
+    Success(thread::Model),
+    Failure { error: String },
+    Forbidden,
+}
+
+impl<S> FromRequest<S> for AcceptAnswerPost
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request(mut req: Request, _state: &S) -> Result<Self> {
+        let auth = req
+            .extract_parts::<AuthSession>()
+            .await
+            .map_err(|_| anyhow!("Auth not found"))?;
+        let Extension(db) = req.extract_parts::<Extension<DatabaseConnection>>().await?;
+        let Path(AcceptAnswerPath {
+            thread_key: thread::Key { id: thread_id, .. },
+            post_key,
+        }) = req.extract_parts::<Path<AcceptAnswerPath>>().await?;
+
+        let user = auth.user.ok_or(anyhow!("Not logged in"))?;
+        let root = db.get_root_post_of(thread_id).await?;
+        if !user.moderator && user.id != root.author_id {
+            return Ok(AcceptAnswerPost::Forbidden);
+        }
+
+        if let Some(post_id) = post_key {
+            let post = db.get_post(post_id).await?;
+            if post.thread_id != thread_id {
+                return Ok(AcceptAnswerPost::Failure {
+                    error: "That post isn't in this thread".into(),
+                });
+            }
+            if post.id == root.id {
+                return Ok(AcceptAnswerPost::Failure {
+                    error: "A thread can't answer itself".into(),
+                });
+            }
+        }
 
-        Ok(ThreadPost(thread.id))
+        let thread = db.set_accepted_answer(thread_id, post_key).await?;
+
+        Ok(AcceptAnswerPost::Success(thread))
     }
 }
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct PostSubmission {
     pub body: String,
+    /// Client-chosen key that makes retrying the same submission safe.
+    #[serde(default)]
+    pub idempotency_key: Option<String>,
+    /// Seconds since the Unix epoch to hold the reply back until.
+    #[serde(default)]
+    pub publish_at: Option<String>,
+}
+
+impl Validate for PostSubmission {
+    fn validate(&self) -> FieldErrors {
+        let mut errors = FieldErrors::default();
+        if self.body.trim().is_empty() {
+            errors.add("body", "Posts can't be empty");
+        }
+        errors
+    }
 }
 
-pub struct PostPost(pub post::Id, pub thread::Id);
+pub enum PostPost {
+    Success(post::Id, thread::Id),
+    Failure(SubmissionError),
+    /// The author's account is younger than [`MinAccountAge`].
+    TooNew(SubmissionError),
+    /// The thread already holds [`MaxPostsPerThread`] posts.
+    ThreadFull(SubmissionError),
+    /// The same submission is already being handled.
+    InFlight(thread::Id),
+    /// The author can't see the thread's opening post, so can't reply to it either.
+    NotFound,
+}
 
 impl<S> FromRequest<S> for PostPost
 where
@@ -93,20 +461,113 @@ where
             .await
             .map_err(|_| anyhow!("Auth not found"))?;
         let Extension(db) = req.extract_parts::<Extension<DatabaseConnection>>().await?;
-        let Extension(sanitizer) = req.extract_parts::<Extension<Sanitizer>>().await?;
-        let Path(thread_id) = req.extract_parts::<Path<thread::Id>>().await?;
+        let Extension(approval) = req.extract_parts::<Extension<ApprovalThreshold>>().await?;
+        let Extension(idempotency) = req
+            .extract_parts::<Extension<IdempotencyKeys<post::Id>>>()
+            .await?;
+        let Extension(min_age) = req.extract_parts::<Extension<MinAccountAge>>().await?;
+        let Extension(max_posts) = req.extract_parts::<Extension<MaxPostsPerThread>>().await?;
+        let renderer = req.extract_parts::<SubmissionRenderer>().await?;
+        let Path(thread::Key { id: thread_id, .. }) =
+            req.extract_parts::<Path<thread::Key>>().await?;
         let Form(post) = req.extract::<Form<PostSubmission>, _>().await?;
 
-        let body = sanitizer.clean(&post.body).to_string();
+        let author = auth.user.ok_or(anyhow!("Not logged in"))?;
+        if !db
+            .get_root_post_of(thread_id)
+            .await?
+            .visible_to(Some(&author))
+        {
+            return Ok(PostPost::NotFound);
+        }
+        if let Some(wait) = min_age.wait(&author) {
+            return Ok(PostPost::TooNew(SubmissionError {
+                error: MinAccountAge::message(wait),
+                field_errors: FieldErrors::default(),
+                thread_id: Some(thread_id),
+                title: String::new(),
+                tags: String::new(),
+                body: post.body,
+            }));
+        }
+        let mut errors = post.validate();
+        let reservation = match post.idempotency_key.filter(|key| !key.is_empty()) {
+            Some(key) => match idempotency.claim(author.id, key) {
+                Claim::New(reservation) => Some(reservation),
+                Claim::Done(post_id) => return Ok(PostPost::Success(post_id, thread_id)),
+                Claim::InFlight => return Ok(PostPost::InFlight(thread_id)),
+            },
+            None => None,
+        };
+        if max_posts.is_full(&db, &author, thread_id).await? {
+            return Ok(PostPost::ThreadFull(SubmissionError {
+                error: max_posts.message(),
+                field_errors: FieldErrors::default(),
+                thread_id: Some(thread_id),
+                title: String::new(),
+                tags: String::new(),
+                body: post.body,
+            }));
+        }
+
+        let body = renderer
+            .body(&post.body)
+            .await?
+            .inspect_err(|err| errors.add("body", err.to_string()));
+        let publish_at = parse_publish_at(post.publish_at.as_deref())
+            .inspect_err(|err| errors.add("publish_at", err));
+        let (Ok(body), Ok(publish_at), true) = (body, publish_at, errors.is_empty()) else {
+            return Ok(PostPost::Failure(SubmissionError {
+                error: String::new(),
+                field_errors: errors,
+                thread_id: Some(thread_id),
+                title: String::new(),
+                tags: String::new(),
+                body: post.body,
+            }));
+        };
+
+        let approved = approval.approves(&db, &author).await?;
 
         let post = db
             .insert_post(post::NewModel {
                 body,
-                author_id: auth.user.ok_or(anyhow!("Not logged in"))?.id,
+                author_id: author.id,
                 thread_id,
+                approved,
+                publish_at,
             })
             .await?;
+        if let Some(reservation) = reservation {
+            reservation.complete(post.id);
+        }
+        db.delete_draft(author.id, thread_id).await?;
+
+        Ok(PostPost::Success(post.id, thread_id))
+    }
+}
+
+/// A rendered body for previewing, produced exactly as [`PostPost`] would store it.
+pub enum PreviewPost {
+    Success(String),
+    Failure { error: String },
+}
+
+impl<S> FromRequest<S> for PreviewPost
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request(mut req: Request, _state: &S) -> Result<Self> {
+        let renderer = req.extract_parts::<SubmissionRenderer>().await?;
+        let Form(post) = req.extract::<Form<PostSubmission>, _>().await?;
 
-        Ok(PostPost(post.id, thread_id))
+        Ok(match renderer.body(&post.body).await? {
+            Ok(body) => PreviewPost::Success(body),
+            Err(err) => PreviewPost::Failure {
+                error: err.to_string(),
+            },
+        })
     }
 }
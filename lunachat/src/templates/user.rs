@@ -1,8 +1,16 @@
-use axum::extract::{FromRequestParts, Path};
+use axum::extract::{FromRequest, FromRequestParts, Path, Query, Request};
 use axum::http::request::Parts;
-use axum::{Extension, RequestPartsExt as _};
+use axum::{Extension, Form, RequestExt as _, RequestPartsExt as _};
+use password_auth::verify_password;
+use serde::Deserialize;
 
+use crate::auth::AuthSession;
 use crate::prelude::*;
+use crate::submission::SubmissionRenderer;
+use crate::timezone::ViewerTimezone;
+
+/// Longest signature accepted, in characters of submitted markup.
+const MAX_SIGNATURE_LEN: usize = 300;
 
 pub struct UserGet {
     pub user: user::Model,
@@ -22,3 +30,158 @@ where
         Ok(UserGet { user })
     }
 }
+
+#[derive(Deserialize)]
+pub struct UserSearchQuery {
+    pub q: String,
+}
+
+pub struct UserSearchGet {
+    pub users: Vec<user::Model>,
+}
+
+impl<S> FromRequestParts<S> for UserSearchGet
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self> {
+        let Extension(db) = parts.extract::<Extension<DatabaseConnection>>().await?;
+        let Query(UserSearchQuery { q }) = parts.extract::<Query<UserSearchQuery>>().await?;
+
+        let users = if q.is_empty() {
+            Vec::new()
+        } else {
+            db.search_users_by_prefix(&q, 10).await?
+        };
+        Ok(UserSearchGet { users })
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SignatureSubmission {
+    pub signature: String,
+}
+
+pub enum SignaturePost {
+    Success(user::Model),
+    Failure { error: String },
+}
+
+impl<S> FromRequest<S> for SignaturePost
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request(mut req: Request, _state: &S) -> Result<Self> {
+        let auth = req
+            .extract_parts::<AuthSession>()
+            .await
+            .map_err(|_| anyhow!("Auth not found"))?;
+        let Extension(db) = req.extract_parts::<Extension<DatabaseConnection>>().await?;
+        let renderer = req.extract_parts::<SubmissionRenderer>().await?;
+        let Form(submission) = req.extract::<Form<SignatureSubmission>, _>().await?;
+
+        let user = auth.user.ok_or(anyhow!("Not logged in"))?;
+        let signature = submission.signature.trim();
+        if signature.chars().count() > MAX_SIGNATURE_LEN {
+            return Ok(SignaturePost::Failure {
+                error: format!("Signatures can be at most {MAX_SIGNATURE_LEN} characters"),
+            });
+        }
+        let signature = match renderer.signature(signature) {
+            Ok(signature) => Some(signature).filter(|signature| !signature.is_empty()),
+            Err(err) => {
+                return Ok(SignaturePost::Failure {
+                    error: err.to_string(),
+                });
+            }
+        };
+
+        let user = db.set_signature(user.id, signature).await?;
+        Ok(SignaturePost::Success(user))
+    }
+}
+
+#[derive(Deserialize)]
+pub struct TimezoneSubmission {
+    pub timezone: String,
+}
+
+/// Sets the timezone the logged-in user sees timestamps in. Blank goes back to UTC.
+pub enum TimezonePost {
+    Success(user::Model),
+    Failure { error: String },
+}
+
+impl<S> FromRequest<S> for TimezonePost
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request(mut req: Request, _state: &S) -> Result<Self> {
+        let auth = req
+            .extract_parts::<AuthSession>()
+            .await
+            .map_err(|_| anyhow!("Auth not found"))?;
+        let Extension(db) = req.extract_parts::<Extension<DatabaseConnection>>().await?;
+        let Form(submission) = req.extract::<Form<TimezoneSubmission>, _>().await?;
+
+        let user = auth.user.ok_or(anyhow!("Not logged in"))?;
+        let timezone = match ViewerTimezone::parse(&submission.timezone) {
+            Ok(timezone) => timezone.map(|timezone| timezone.name().to_string()),
+            Err(error) => return Ok(TimezonePost::Failure { error }),
+        };
+
+        let user = db.set_timezone(user.id, timezone).await?;
+        Ok(TimezonePost::Success(user))
+    }
+}
+
+#[derive(Deserialize)]
+pub struct DeleteConfirmation {
+    pub password: String,
+}
+
+/// Deletes the logged-in user's own account once they've confirmed their password.
+pub enum UserDeletePost {
+    Success(user::Id),
+    Failure { error: String },
+}
+
+impl<S> FromRequest<S> for UserDeletePost
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request(mut req: Request, _state: &S) -> Result<Self> {
+        let mut auth = req
+            .extract_parts::<AuthSession>()
+            .await
+            .map_err(|_| anyhow!("Auth not found"))?;
+        let Extension(db) = req.extract_parts::<Extension<DatabaseConnection>>().await?;
+        let Extension(mode) = req.extract_parts::<Extension<user::DeleteMode>>().await?;
+        let Form(confirmation) = req.extract::<Form<DeleteConfirmation>, _>().await?;
+
+        let user = auth.user.clone().ok_or(anyhow!("Not logged in"))?;
+        let hash = user.password.clone();
+        let confirmed = tokio::task::spawn_blocking(move || {
+            verify_password(confirmation.password, &hash).is_ok()
+        })
+        .await?;
+        if !confirmed {
+            return Ok(UserDeletePost::Failure {
+                error: "Password incorrect".into(),
+            });
+        }
+
+        db.delete_user(user.id, mode).await?;
+        auth.logout().await.map_err(Box::new)?;
+
+        Ok(UserDeletePost::Success(user.id))
+    }
+}
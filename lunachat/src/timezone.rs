@@ -0,0 +1,64 @@
+use std::borrow::Borrow;
+
+use axum::RequestPartsExt as _;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+
+use crate::auth::AuthSession;
+use crate::prelude::*;
+
+/// The timezone absolute timestamps are shown in: the logged-in user's chosen one, or UTC
+/// for guests and users who haven't picked one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ViewerTimezone(pub Tz);
+
+impl Default for ViewerTimezone {
+    fn default() -> Self {
+        Self(Tz::UTC)
+    }
+}
+
+impl ViewerTimezone {
+    /// Parses an IANA timezone name such as `Europe/Berlin`. Blank means no preference.
+    pub fn parse(name: &str) -> Result<Option<Tz>, String> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Ok(None);
+        }
+        name.parse::<Tz>()
+            .map(Some)
+            .map_err(|_| format!("Unknown timezone \"{name}\""))
+    }
+
+    pub fn of(user: Option<&user::Model>) -> Self {
+        user.and_then(|user| user.timezone.as_deref())
+            .and_then(|name| name.parse().ok())
+            .map(Self)
+            .unwrap_or_default()
+    }
+
+    pub fn format(&self, at: impl Borrow<DateTime<Utc>>) -> String {
+        at.borrow()
+            .with_timezone(&self.0)
+            .format("%Y-%m-%d %H:%M %Z")
+            .to_string()
+    }
+}
+
+impl<S> FromRequestParts<S> for ViewerTimezone
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self> {
+        let auth = parts
+            .extract::<AuthSession>()
+            .await
+            .map_err(|_| anyhow!("Auth not found"))?;
+
+        Ok(Self::of(auth.user.as_ref()))
+    }
+}
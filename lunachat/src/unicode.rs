@@ -0,0 +1,113 @@
+use std::env;
+
+use unicode_normalization::UnicodeNormalization as _;
+
+use crate::prelude::*;
+
+/// Invisible characters that are only ever pasted in by accident or to dodge filters.
+/// The zero-width joiner isn't here because emoji sequences need it; see [`strip_invisible`].
+const ZERO_WIDTH: &[char] = &['\u{200B}', '\u{200C}', '\u{2060}', '\u{FEFF}', '\u{00AD}'];
+const ZERO_WIDTH_JOINER: char = '\u{200D}';
+
+/// NFC normalization and invisible-character stripping for submitted text, so visually
+/// identical titles and bodies are stored identically. On unless `LUNACHAT_NORMALIZE_TEXT`
+/// is `false`.
+#[derive(Clone, Copy)]
+pub struct TextNormalizer {
+    pub enabled: bool,
+}
+
+impl TextNormalizer {
+    pub fn from_env() -> Result<Self> {
+        let enabled = match env::var("LUNACHAT_NORMALIZE_TEXT") {
+            Ok(enabled) => enabled.parse()?,
+            Err(_) => true,
+        };
+        Ok(Self { enabled })
+    }
+
+    pub fn apply(&self, text: &str) -> String {
+        if !self.enabled {
+            return text.to_string();
+        }
+        strip_invisible(&text.nfc().collect::<String>())
+    }
+}
+
+/// Drops zero-width and control characters, keeping line breaks and tabs. A zero-width
+/// joiner is only kept between two symbols, where it's part of an emoji sequence.
+fn strip_invisible(text: &str) -> String {
+    let is_symbol = |c: char| !c.is_alphanumeric() && !c.is_whitespace() && !c.is_control();
+    let chars = text.chars().collect::<Vec<_>>();
+    chars
+        .iter()
+        .enumerate()
+        .filter(|&(i, &c)| match c {
+            '\n' | '\r' | '\t' => true,
+            ZERO_WIDTH_JOINER => {
+                i > 0
+                    && chars.get(i + 1).is_some_and(|&next| is_symbol(next))
+                    && is_symbol(chars[i - 1])
+            }
+            c => !c.is_control() && !ZERO_WIDTH.contains(&c),
+        })
+        .map(|(_, &c)| c)
+        .collect()
+}
+
+/// Whether `username` is free of anything that makes it look like a different name:
+/// invisible characters, and compatibility forms like fullwidth letters that NFKC would
+/// fold into ordinary ones.
+pub fn is_plain_username(username: &str) -> bool {
+    strip_invisible(username) == username
+        && !username.contains(ZERO_WIDTH_JOINER)
+        && username.nfkc().eq(username.chars())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn emoji_sequences_keep_their_joiners() {
+        for emoji in [
+            "👨\u{200D}👩\u{200D}👧",
+            "👁\u{FE0F}\u{200D}🗨\u{FE0F}",
+            "🏳\u{FE0F}\u{200D}🌈",
+        ] {
+            assert_eq!(strip_invisible(emoji), emoji);
+        }
+    }
+
+    #[test]
+    fn stray_joiners_are_dropped() {
+        assert_eq!(strip_invisible("ad\u{200D}min"), "admin");
+        assert_eq!(strip_invisible("\u{200D}👍"), "👍");
+        assert_eq!(strip_invisible("👍\u{200D}"), "👍");
+    }
+
+    #[test]
+    fn invisible_and_control_characters_are_dropped() {
+        assert_eq!(
+            strip_invisible("a\u{200B}b\u{FEFF}c\u{00AD}d\u{7}e"),
+            "abcde"
+        );
+        assert_eq!(strip_invisible("line\r\n\tnext"), "line\r\n\tnext");
+    }
+
+    #[test]
+    fn text_is_composed() {
+        let normalizer = TextNormalizer { enabled: true };
+        assert_eq!(normalizer.apply("cafe\u{301}"), "café");
+        let disabled = TextNormalizer { enabled: false };
+        assert_eq!(disabled.apply("cafe\u{301}\u{200B}"), "cafe\u{301}\u{200B}");
+    }
+
+    #[test]
+    fn lookalike_usernames_are_not_plain() {
+        assert!(is_plain_username("luna_chat"));
+        assert!(!is_plain_username("ａｄｍｉｎ"));
+        assert!(!is_plain_username("ad\u{200B}min"));
+        assert!(!is_plain_username("👨\u{200D}👩"));
+    }
+}
@@ -0,0 +1,103 @@
+use std::collections::HashSet;
+use std::env;
+use std::sync::Arc;
+
+/// Names that could pass for staff or the system.
+const DEFAULT_RESERVED: &[&str] = &[
+    "admin",
+    "administrator",
+    "moderator",
+    "mod",
+    "root",
+    "system",
+    "staff",
+    "lunachat",
+];
+
+/// Usernames nobody may register: [`DEFAULT_RESERVED`] plus the comma-separated
+/// `LUNACHAT_RESERVED_USERNAMES`. Names are compared after [`normalize`], so `Adm1n`
+/// is just as reserved as `admin`.
+#[derive(Clone)]
+pub struct ReservedUsernames(Arc<HashSet<String>>);
+
+impl ReservedUsernames {
+    pub fn from_env() -> Self {
+        let configured = env::var("LUNACHAT_RESERVED_USERNAMES").unwrap_or_default();
+        Self::new(configured.split(','))
+    }
+
+    /// [`DEFAULT_RESERVED`] plus `names`.
+    pub fn new<'a>(names: impl IntoIterator<Item = &'a str>) -> Self {
+        let names = DEFAULT_RESERVED
+            .iter()
+            .copied()
+            .chain(names)
+            .map(normalize)
+            .filter(|name| !name.is_empty())
+            .collect();
+        Self(Arc::new(names))
+    }
+
+    pub fn contains(&self, username: &str) -> bool {
+        self.0.contains(&normalize(username))
+    }
+}
+
+/// Lowercases `username`, undoes common leetspeak substitutions and drops separators.
+fn normalize(username: &str) -> String {
+    username
+        .trim()
+        .to_lowercase()
+        .chars()
+        .filter_map(|c| match c {
+            '0' => Some('o'),
+            '1' | '!' | '|' => Some('i'),
+            '3' => Some('e'),
+            '4' | '@' => Some('a'),
+            '5' | '$' => Some('s'),
+            '7' => Some('t'),
+            '_' | '-' | '.' | ' ' => None,
+            c => Some(c),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reserved_names_ignore_case_and_separators() {
+        let reserved = ReservedUsernames::new([]);
+        assert!(reserved.contains("Admin"));
+        assert!(reserved.contains(" ADMIN "));
+        assert!(reserved.contains("mod_erator"));
+        assert!(reserved.contains("Luna.Chat"));
+    }
+
+    #[test]
+    fn leetspeak_spellings_are_reserved() {
+        let reserved = ReservedUsernames::new([]);
+        assert!(reserved.contains("adm1n"));
+        assert!(reserved.contains("@dm!n"));
+        assert!(reserved.contains("5y5t3m"));
+        assert!(reserved.contains("r00t"));
+    }
+
+    #[test]
+    fn other_names_are_allowed() {
+        let reserved = ReservedUsernames::new([]);
+        assert!(!reserved.contains("luna"));
+        assert!(!reserved.contains("admiral"));
+        assert!(!reserved.contains("moddy"));
+        assert!(!reserved.contains("rooty"));
+    }
+
+    #[test]
+    fn configured_names_are_added() {
+        let reserved = ReservedUsernames::new(["Luna", " ", ""]);
+        assert!(reserved.contains("luna"));
+        assert!(reserved.contains("admin"));
+        assert!(!reserved.contains(""));
+    }
+}
@@ -0,0 +1,39 @@
+use derive_more::{Deref, DerefMut};
+use serde::Serialize;
+
+/// A problem with one field of a submitted form.
+#[derive(Clone, Debug, Serialize)]
+pub struct FieldError {
+    /// The form field's `name`.
+    pub field: &'static str,
+    pub message: String,
+}
+
+/// Every problem found with a form, so they can all be fixed in one go.
+#[derive(Clone, Debug, Default, Serialize, Deref, DerefMut)]
+pub struct FieldErrors(pub Vec<FieldError>);
+
+impl FieldErrors {
+    pub fn add(&mut self, field: &'static str, message: impl Into<String>) {
+        self.0.push(FieldError {
+            field,
+            message: message.into(),
+        });
+    }
+
+    /// The messages for `field`, for showing next to it.
+    pub fn for_field(&self, field: &str) -> Vec<&str> {
+        self.0
+            .iter()
+            .filter(|error| error.field == field)
+            .map(|error| error.message.as_str())
+            .collect()
+    }
+}
+
+/// Checks a submitted form for everything that can be judged from the form alone.
+/// Anything needing the database, like whether a username is taken, is left to the
+/// handler, which adds to the same [`FieldErrors`].
+pub trait Validate {
+    fn validate(&self) -> FieldErrors;
+}
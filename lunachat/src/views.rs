@@ -0,0 +1,56 @@
+use std::env;
+use std::time::Duration;
+
+use axum_login::tower_sessions::Session;
+use chrono::{DateTime, Utc};
+
+use crate::prelude::*;
+
+/// Session key holding the threads this session has recently been counted as viewing.
+const RECENT_VIEWS_KEY: &str = "lunachat.recent_views";
+
+/// Most threads remembered per session. The oldest view is forgotten first.
+const MAX_RECENT_VIEWS: usize = 50;
+
+/// How long a session's view of a thread keeps its reloads from counting again, from
+/// `LUNACHAT_VIEW_WINDOW_SECS`. Defaults to 30 minutes.
+#[derive(Clone, Copy)]
+pub struct ViewWindow(pub Duration);
+
+impl ViewWindow {
+    pub fn from_env() -> Result<Self> {
+        Ok(Self(match env::var("LUNACHAT_VIEW_WINDOW_SECS") {
+            Ok(secs) => Duration::from_secs(secs.parse()?),
+            Err(_) => Duration::from_secs(30 * 60),
+        }))
+    }
+
+    /// Counts a view of `thread_id` unless this session already viewed it within the
+    /// window.
+    pub async fn record(
+        &self,
+        session: &Session,
+        db: &DatabaseConnection,
+        thread_id: thread::Id,
+    ) -> Result<()> {
+        let now = Utc::now();
+        let window = chrono::Duration::from_std(self.0)?;
+        let mut recent = session
+            .get::<Vec<(thread::Id, DateTime<Utc>)>>(RECENT_VIEWS_KEY)
+            .await?
+            .unwrap_or_default();
+        recent.retain(|(_, viewed_at)| now - *viewed_at < window);
+        if recent.iter().any(|(id, _)| *id == thread_id) {
+            return Ok(());
+        }
+
+        db.increment_thread_views(thread_id).await?;
+
+        recent.push((thread_id, now));
+        if recent.len() > MAX_RECENT_VIEWS {
+            recent.remove(0);
+        }
+        session.insert(RECENT_VIEWS_KEY, recent).await?;
+        Ok(())
+    }
+}
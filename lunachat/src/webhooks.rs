@@ -0,0 +1,278 @@
+use std::collections::HashSet;
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+
+use hmac::{Hmac, Mac as _};
+use serde::Serialize;
+use sha2::Sha256;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc::{Receiver, Sender, channel};
+use url::Url;
+
+use crate::api::PublicUser;
+use crate::prelude::*;
+
+/// Header carrying `sha256=` and the hex HMAC-SHA256 of the body, keyed with
+/// `LUNACHAT_WEBHOOK_SECRET`.
+pub const SIGNATURE_HEADER: &str = "X-Lunachat-Signature";
+
+/// Events waiting to be delivered. Anything past this is dropped rather than holding up
+/// the request that caused it.
+const QUEUE_SIZE: usize = 256;
+
+/// Attempts per delivery, waiting twice as long after each failure.
+const MAX_ATTEMPTS: u32 = 5;
+const FIRST_RETRY: Duration = Duration::from_secs(1);
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Something that happened on the forum, POSTed as JSON to every configured webhook.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "event", content = "data")]
+pub enum WebhookEvent {
+    #[serde(rename = "thread.created")]
+    ThreadCreated(thread::Model),
+    #[serde(rename = "post.created")]
+    PostCreated(post::Model),
+    #[serde(rename = "user.registered")]
+    UserRegistered(PublicUser),
+    #[serde(rename = "moderation")]
+    Moderation(ModerationAction),
+}
+
+impl WebhookEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            WebhookEvent::ThreadCreated(_) => "thread.created",
+            WebhookEvent::PostCreated(_) => "post.created",
+            WebhookEvent::UserRegistered(_) => "user.registered",
+            WebhookEvent::Moderation(_) => "moderation",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum ModerationAction {
+    PostApproved {
+        post_id: post::Id,
+        thread_id: thread::Id,
+    },
+    ThreadPinned {
+        thread_id: thread::Id,
+        pinned: bool,
+    },
+    UserDeleted {
+        user_id: user::Id,
+    },
+    Maintenance {
+        enabled: bool,
+    },
+}
+
+/// Outbound webhooks, configured from the environment:
+///
+/// - `LUNACHAT_WEBHOOK_URLS`: comma-separated URLs to POST events to. Unset disables
+///   webhooks.
+/// - `LUNACHAT_WEBHOOK_EVENTS`: comma-separated [event names](WebhookEvent::name) to send.
+///   Defaults to all of them.
+/// - `LUNACHAT_WEBHOOK_SECRET`: key for the [`SIGNATURE_HEADER`]. Unsigned if unset.
+///
+/// Posts are announced once everyone can see them, so posts held for approval or
+/// scheduled for later are sent when they're approved or published rather than when
+/// they're written.
+#[derive(Clone, Default)]
+pub struct Webhooks {
+    queue: Option<Sender<WebhookEvent>>,
+}
+
+struct Config {
+    urls: Vec<Url>,
+    events: Option<HashSet<String>>,
+    secret: Option<Vec<u8>>,
+    client: reqwest::Client,
+}
+
+impl Webhooks {
+    pub fn from_env() -> Result<Self> {
+        let urls = match env::var("LUNACHAT_WEBHOOK_URLS") {
+            Ok(urls) => urls
+                .split(',')
+                .map(str::trim)
+                .filter(|url| !url.is_empty())
+                .map(Url::parse)
+                .collect::<Result<Vec<_>, _>>()?,
+            Err(_) => Vec::new(),
+        };
+        if urls.is_empty() {
+            return Ok(Self::default());
+        }
+
+        let config = Arc::new(Config {
+            urls,
+            events: env::var("LUNACHAT_WEBHOOK_EVENTS").ok().map(|events| {
+                events
+                    .split(',')
+                    .map(|event| event.trim().to_ascii_lowercase())
+                    .filter(|event| !event.is_empty())
+                    .collect()
+            }),
+            secret: env::var("LUNACHAT_WEBHOOK_SECRET")
+                .ok()
+                .map(String::into_bytes),
+            client: reqwest::Client::builder()
+                .timeout(DELIVERY_TIMEOUT)
+                .build()?,
+        });
+
+        let (queue, events) = channel(QUEUE_SIZE);
+        tokio::spawn(deliver_all(config, events));
+        let webhooks = Self { queue: Some(queue) };
+        webhooks.forward_broadcasts();
+        Ok(webhooks)
+    }
+
+    /// Queues `event` for delivery without waiting on it.
+    pub fn notify(&self, event: WebhookEvent) {
+        let Some(queue) = &self.queue else {
+            return;
+        };
+        if let Err(err) = queue.try_send(event) {
+            tracing::warn!("Dropped webhook event: {err}");
+        }
+    }
+
+    /// Turns new threads, posts and users into events.
+    fn forward_broadcasts(&self) {
+        let webhooks = self.clone();
+        let mut threads = thread::BROADCAST.subscribe();
+        let mut posts = post::BROADCAST.subscribe();
+        let mut users = user::BROADCAST.subscribe();
+        tokio::spawn(async move {
+            loop {
+                let event = tokio::select! {
+                    event = threads.recv() => match event {
+                        Ok(BroadcastEvent::Create(thread)) => WebhookEvent::ThreadCreated(thread),
+                        Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                        Err(RecvError::Closed) => break,
+                    },
+                    event = posts.recv() => match event {
+                        // Posts are only updated when they're approved or published.
+                        Ok(BroadcastEvent::Create(post) | BroadcastEvent::Update(post))
+                            if post.is_public() =>
+                        {
+                            WebhookEvent::PostCreated(post)
+                        }
+                        Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                        Err(RecvError::Closed) => break,
+                    },
+                    event = users.recv() => match event {
+                        Ok(BroadcastEvent::Create(user)) if user.username != user::DELETED_USERNAME => {
+                            WebhookEvent::UserRegistered(user.into())
+                        }
+                        Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                        Err(RecvError::Closed) => break,
+                    },
+                };
+                webhooks.notify(event);
+            }
+        });
+    }
+}
+
+async fn deliver_all(config: Arc<Config>, mut events: Receiver<WebhookEvent>) {
+    while let Some(event) = events.recv().await {
+        if let Some(filter) = &config.events
+            && !filter.contains(event.name())
+        {
+            continue;
+        }
+        let body = match serde_json::to_vec(&event) {
+            Ok(body) => body,
+            Err(err) => {
+                tracing::warn!("Failed to serialize webhook event: {err}");
+                continue;
+            }
+        };
+        let signature = config.secret.as_deref().map(|secret| sign(secret, &body));
+        for url in &config.urls {
+            // Each target retries on its own so one slow receiver doesn't delay the rest.
+            tokio::spawn(deliver(
+                config.clone(),
+                url.clone(),
+                body.clone(),
+                signature.clone(),
+            ));
+        }
+    }
+}
+
+async fn deliver(config: Arc<Config>, url: Url, body: Vec<u8>, signature: Option<String>) {
+    let mut retry = FIRST_RETRY;
+    for attempt in 1..=MAX_ATTEMPTS {
+        let mut request = config
+            .client
+            .post(url.clone())
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body.clone());
+        if let Some(signature) = &signature {
+            request = request.header(SIGNATURE_HEADER, signature);
+        }
+        match request
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+        {
+            Ok(_) => return,
+            Err(err) => {
+                tracing::debug!("Webhook delivery to {url} failed (attempt {attempt}): {err}")
+            }
+        }
+        tokio::time::sleep(retry).await;
+        retry *= 2;
+    }
+    tracing::warn!("Gave up delivering webhook to {url} after {MAX_ATTEMPTS} attempts");
+}
+
+/// The [`SIGNATURE_HEADER`] value for `body`.
+pub fn sign(secret: &[u8], body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC takes keys of any size");
+    mac.update(body);
+    let digest = mac.finalize().into_bytes();
+    format!(
+        "sha256={}",
+        digest
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<String>()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn signature_is_hex_hmac_sha256() {
+        // RFC 4231, test case 2.
+        assert_eq!(
+            sign(b"Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn events_are_tagged_with_their_name() {
+        let event = WebhookEvent::Moderation(ModerationAction::Maintenance { enabled: true });
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            json!({
+                "event": "moderation",
+                "data": {"action": "maintenance", "enabled": true},
+            })
+        );
+        assert_eq!(event.name(), "moderation");
+    }
+}
@@ -0,0 +1,168 @@
+use std::env;
+use std::path::PathBuf;
+use std::sync::{Arc, PoisonError, RwLock};
+
+use derive_more::Display;
+use regex::{Captures, Regex};
+
+use crate::prelude::*;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum FilterMode {
+    /// Replace the phrase with asterisks.
+    Replace,
+    /// Refuse the whole submission.
+    Reject,
+}
+
+struct Rule {
+    pattern: Regex,
+    mode: FilterMode,
+}
+
+#[derive(Debug, Display)]
+#[display("\"{_0}\" isn't allowed here")]
+pub struct FilteredContent(pub String);
+
+/// Banned-phrase filter loaded from the file at `LUNACHAT_WORDFILTER`.
+///
+/// Each non-empty line is a phrase, optionally prefixed by `replace` or `reject`
+/// (the default is `replace`). Lines starting with `#` are comments. Matching is
+/// case-insensitive and only on whole words, so `ass` doesn't match `class`.
+#[derive(Clone, Default)]
+pub struct WordFilter {
+    rules: Arc<RwLock<Vec<Rule>>>,
+    path: Option<Arc<PathBuf>>,
+}
+
+impl WordFilter {
+    pub fn from_env() -> Result<Self> {
+        let Ok(path) = env::var("LUNACHAT_WORDFILTER") else {
+            return Ok(Self::default());
+        };
+        let filter = Self {
+            rules: Default::default(),
+            path: Some(Arc::new(path.into())),
+        };
+        filter.reload()?;
+        Ok(filter)
+    }
+
+    /// Re-reads the filter file. On failure the previous rules stay in effect.
+    pub fn reload(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let rules = parse_rules(&std::fs::read_to_string(path.as_ref())?)?;
+        *self.rules.write().unwrap_or_else(PoisonError::into_inner) = rules;
+        Ok(())
+    }
+
+    /// Reloads the filter file whenever the process receives SIGHUP.
+    pub fn reload_on_sighup(&self) -> Result<()> {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{SignalKind, signal};
+
+            let filter = self.clone();
+            let mut hangup = signal(SignalKind::hangup())?;
+            tokio::spawn(async move {
+                while hangup.recv().await.is_some() {
+                    match filter.reload() {
+                        Ok(()) => tracing::info!("Reloaded word filter"),
+                        Err(err) => tracing::warn!("Failed to reload word filter: {err}"),
+                    }
+                }
+            });
+        }
+        Ok(())
+    }
+
+    pub fn apply(&self, text: &str) -> Result<String, FilteredContent> {
+        let rules = self.rules.read().unwrap_or_else(PoisonError::into_inner);
+
+        for rule in rules.iter().filter(|rule| rule.mode == FilterMode::Reject) {
+            if let Some(found) = rule.pattern.find(text) {
+                return Err(FilteredContent(found.as_str().to_string()));
+            }
+        }
+
+        let mut text = text.to_string();
+        for rule in rules.iter().filter(|rule| rule.mode == FilterMode::Replace) {
+            text = rule
+                .pattern
+                .replace_all(&text, |caps: &Captures| "*".repeat(caps[0].chars().count()))
+                .into_owned();
+        }
+        Ok(text)
+    }
+}
+
+fn parse_rules(config: &str) -> Result<Vec<Rule>> {
+    config
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let (mode, phrase) = match line.split_once(char::is_whitespace) {
+                Some(("reject", phrase)) => (FilterMode::Reject, phrase.trim()),
+                Some(("replace", phrase)) => (FilterMode::Replace, phrase.trim()),
+                _ => (FilterMode::Replace, line),
+            };
+            let pattern = Regex::new(&format!(r"(?i)\b{}\b", regex::escape(phrase)))?;
+            Ok(Rule { pattern, mode })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(config: &str) -> WordFilter {
+        WordFilter {
+            rules: Arc::new(RwLock::new(parse_rules(config).unwrap())),
+            path: None,
+        }
+    }
+
+    #[test]
+    fn only_whole_words_are_replaced() {
+        let filter = filter("ass");
+        assert_eq!(
+            filter.apply("a class of glass").unwrap(),
+            "a class of glass"
+        );
+        assert_eq!(filter.apply("what an Ass.").unwrap(), "what an ***.");
+    }
+
+    #[test]
+    fn phrases_are_replaced_by_character() {
+        let filter = filter("replace darn it\nreplace façade");
+        assert_eq!(
+            filter.apply("Darn it, a FAÇADE").unwrap(),
+            "*******, a ******"
+        );
+    }
+
+    #[test]
+    fn rejected_phrases_refuse_the_submission() {
+        let filter = filter("# spam\nreject buy now\nheck");
+        let Err(FilteredContent(found)) = filter.apply("heck, BUY NOW") else {
+            panic!("submission was accepted");
+        };
+        assert_eq!(found, "BUY NOW");
+        assert_eq!(
+            filter.apply("buy nowhere, heck").unwrap(),
+            "buy nowhere, ****"
+        );
+    }
+
+    #[test]
+    fn empty_filter_changes_nothing() {
+        assert_eq!(
+            WordFilter::default().apply("anything goes").unwrap(),
+            "anything goes"
+        );
+    }
+}